@@ -0,0 +1,108 @@
+use crate::vec3::Color;
+
+/// Returns the luminance of a linear rgb color
+fn luminance(color: Color) -> f64 {
+    let (r, g, b) = color.xyz();
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// Running mean and variance of the samples taken for a pixel
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PixelStats {
+    count: u32,
+    sum: Color,
+    mean_luminance: f64,
+    m2_luminance: f64,
+}
+
+impl PixelStats {
+    /// Add a sample to the statistics
+    pub fn add(&mut self, color: Color) {
+        self.count += 1;
+        self.sum += color;
+
+        // Welford's online algorithm for the variance of the luminance
+        let l = luminance(color);
+        let delta = l - self.mean_luminance;
+        self.mean_luminance += delta / self.count as f64;
+        self.m2_luminance += delta * (l - self.mean_luminance);
+    }
+
+    /// Returns the number of samples taken
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Returns the mean color of the samples
+    pub fn mean(&self) -> Color {
+        if self.count == 0 {
+            Color::new(0.0, 0.0, 0.0)
+        } else {
+            self.sum / self.count as f64
+        }
+    }
+
+    /// Returns the sample variance of the luminance
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            0.0
+        } else {
+            self.m2_luminance / (self.count - 1) as f64
+        }
+    }
+
+    /// Returns the standard error of the mean luminance relative to the mean luminance
+    pub fn relative_error(&self) -> f64 {
+        let standard_error = (self.variance() / self.count.max(1) as f64).sqrt();
+
+        // avoid dividing by zero in dark pixels by relating to a small floor
+        standard_error / self.mean_luminance.max(1e-3)
+    }
+}
+
+/// Stops sampling a pixel once its estimated error falls below a threshold
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+    min_samples: u32,
+    max_samples: u32,
+    threshold: f64,
+}
+
+impl AdaptiveSampling {
+    /// Sample each pixel at least min_samples and at most max_samples times
+    ///
+    /// In between, sampling stops as soon as the standard error of the mean
+    /// luminance relative to the mean luminance drops below threshold.
+    pub fn new(min_samples: u32, max_samples: u32, threshold: f64) -> Self {
+        let min_samples = min_samples.max(2);
+
+        Self {
+            min_samples,
+            max_samples: max_samples.max(min_samples),
+            threshold,
+        }
+    }
+
+    pub fn max_samples(&self) -> u32 {
+        self.max_samples
+    }
+
+    /// Returns whether a pixel with the given statistics needs no more samples
+    pub fn converged(&self, stats: &PixelStats) -> bool {
+        stats.count() >= self.max_samples
+            || (stats.count() >= self.min_samples && stats.relative_error() < self.threshold)
+    }
+
+    /// Returns a heatmap color for the number of samples taken, from blue for
+    /// min_samples over green to red for max_samples
+    pub fn heatmap_color(&self, count: u32) -> Color {
+        let range = (self.max_samples - self.min_samples).max(1) as f64;
+        let t = ((count.saturating_sub(self.min_samples)) as f64 / range).clamp(0.0, 1.0);
+
+        if t < 0.5 {
+            Color::new(0.0, 2.0 * t, 1.0 - 2.0 * t)
+        } else {
+            Color::new(2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use crate::cam::CameraBuilder;
+use crate::vec3::{Point3, Vec3};
+use std::ops::{Add, Mul, Sub};
+
+/// The view of a camera at a point in time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    time: f64,
+    look_from: Point3,
+    look_at: Point3,
+    vfov: f64,
+    focus_dist: f64,
+}
+
+impl Keyframe {
+    pub fn new(time: f64, look_from: Point3, look_at: Point3, vfov: f64, focus_dist: f64) -> Self {
+        Self {
+            time,
+            look_from,
+            look_at,
+            vfov,
+            focus_dist,
+        }
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn look_from(&self) -> Point3 {
+        self.look_from
+    }
+
+    pub fn look_at(&self) -> Point3 {
+        self.look_at
+    }
+
+    pub fn vfov(&self) -> f64 {
+        self.vfov
+    }
+
+    pub fn focus_dist(&self) -> f64 {
+        self.focus_dist
+    }
+}
+
+/// How values are interpolated between keyframes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    /// A smooth curve through all keyframes
+    CatmullRom,
+}
+
+fn lerp<T>(a: T, b: T, t: f64) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>,
+{
+    a + (b - a) * t
+}
+
+/// Evaluate the uniform Catmull-Rom spline between p1 and p2
+fn catmull_rom<T>(p0: T, p1: T, p2: T, p3: T, t: f64) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>,
+{
+    let (t2, t3) = (t * t, t * t * t);
+
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+/// Spherically interpolate between two unit vectors
+///
+/// Opposite vectors are interpolated by turning around up, or around any axis
+/// perpendicular to them if up is parallel to them.
+fn slerp(a: Vec3, b: Vec3, t: f64, up: Vec3) -> Vec3 {
+    let cos_theta = a.dot(b).clamp(-1.0, 1.0);
+    let theta = cos_theta.acos();
+
+    if theta.sin().abs() < 1e-6 {
+        // nearly parallel vectors are interpolated linearly
+        if cos_theta > 0.0 {
+            return lerp(a, b, t).unit();
+        }
+
+        let axis = up - up.dot(a) * a;
+        let axis = if axis.near_zero() {
+            let helper = if a.x().abs() > 0.9 {
+                Vec3::new(0.0, 1.0, 0.0)
+            } else {
+                Vec3::new(1.0, 0.0, 0.0)
+            };
+            a.cross(helper).unit()
+        } else {
+            axis.unit()
+        };
+        let angle = t * std::f64::consts::PI;
+
+        return a * angle.cos() + axis.cross(a) * angle.sin();
+    }
+
+    (((1.0 - t) * theta).sin() * a + (t * theta).sin() * b) / theta.sin()
+}
+
+/// A camera moving through keyframes
+///
+/// Between keyframes the view is interpolated, before the first and after the
+/// last one it is held.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraAnimation {
+    keyframes: Vec<Keyframe>,
+    interpolation: Interpolation,
+    slerp: bool,
+    vup: Vec3,
+}
+
+impl CameraAnimation {
+    /// Create a new CameraAnimation, the keyframes may be given in any order
+    pub fn new(mut keyframes: Vec<Keyframe>, interpolation: Interpolation) -> Self {
+        assert!(!keyframes.is_empty(), "camera animation without keyframes");
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        Self {
+            keyframes,
+            interpolation,
+            slerp: false,
+            vup: Vec3::new(0.0, 1.0, 0.0),
+        }
+    }
+
+    /// Interpolate the view direction along great circles instead of
+    /// interpolating look_at
+    ///
+    /// This turns the camera at a steady rate. The distance to look_at is
+    /// interpolated separately, and the roll is given by the view up vector.
+    pub fn slerp(mut self, slerp: bool) -> Self {
+        self.slerp = slerp;
+        self
+    }
+
+    /// Sets the view up vector of the camera, which the view turns around
+    /// between keyframes looking in opposite directions
+    pub fn view_up(mut self, view_up: Vec3) -> Self {
+        self.vup = view_up;
+        self
+    }
+
+    pub fn start(&self) -> f64 {
+        self.keyframes[0].time
+    }
+
+    pub fn end(&self) -> f64 {
+        self.keyframes[self.keyframes.len() - 1].time
+    }
+
+    /// Returns the interpolated view at a point in time
+    pub fn at(&self, time: f64) -> Keyframe {
+        let n = self.keyframes.len();
+        let next = self.keyframes.partition_point(|key| key.time <= time);
+
+        if next == 0 {
+            return Keyframe {
+                time,
+                ..self.keyframes[0]
+            };
+        }
+        if next == n {
+            return Keyframe {
+                time,
+                ..self.keyframes[n - 1]
+            };
+        }
+
+        let (i1, i2) = (next - 1, next);
+        let (i0, i3) = (i1.saturating_sub(1), (i2 + 1).min(n - 1));
+        let keys = [
+            &self.keyframes[i0],
+            &self.keyframes[i1],
+            &self.keyframes[i2],
+            &self.keyframes[i3],
+        ];
+        let t = (time - keys[1].time) / (keys[2].time - keys[1].time);
+
+        let look_from = self.interpolate(keys, t, |key| key.look_from);
+        let look_at = if self.slerp {
+            let direction = |key: &Keyframe| (key.look_at - key.look_from).unit();
+            let distance = self.interpolate(keys, t, |key| (key.look_at - key.look_from).length());
+
+            look_from + distance * slerp(direction(keys[1]), direction(keys[2]), t, self.vup)
+        } else {
+            self.interpolate(keys, t, |key| key.look_at)
+        };
+
+        Keyframe {
+            time,
+            look_from,
+            look_at,
+            vfov: self.interpolate(keys, t, |key| key.vfov),
+            focus_dist: self.interpolate(keys, t, |key| key.focus_dist),
+        }
+    }
+
+    /// Interpolate a value between the middle two of four consecutive keyframes
+    fn interpolate<T>(&self, keys: [&Keyframe; 4], t: f64, value: impl Fn(&Keyframe) -> T) -> T
+    where
+        T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>,
+    {
+        match self.interpolation {
+            Interpolation::Linear => lerp(value(keys[1]), value(keys[2]), t),
+            Interpolation::CatmullRom => catmull_rom(
+                value(keys[0]),
+                value(keys[1]),
+                value(keys[2]),
+                value(keys[3]),
+                t,
+            ),
+        }
+    }
+
+    /// Returns a copy of the camera builder with the view at a point in time
+    pub fn apply(&self, time: f64, camera: &CameraBuilder) -> CameraBuilder {
+        let key = self.at(time);
+        let mut camera = camera.clone();
+
+        camera
+            .look_from(key.look_from)
+            .look_at(key.look_at)
+            .vertical_fov(key.vfov)
+            .focus_dist(key.focus_dist);
+        camera
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframes() -> Vec<Keyframe> {
+        vec![
+            Keyframe::new(
+                1.0,
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(0.0, 0.0, 1.0),
+                40.0,
+                1.0,
+            ),
+            Keyframe::new(
+                0.0,
+                Point3::new(-2.0, 1.0, 0.0),
+                Point3::new(0.0, 0.0, 1.0),
+                60.0,
+                2.0,
+            ),
+            Keyframe::new(
+                3.0,
+                Point3::new(4.0, 0.0, 2.0),
+                Point3::new(1.0, 1.0, 1.0),
+                20.0,
+                5.0,
+            ),
+        ]
+    }
+
+    #[test]
+    fn hold_outside_of_keyframes() {
+        let animation = CameraAnimation::new(keyframes(), Interpolation::CatmullRom);
+
+        let before = animation.at(-1.0);
+        assert_eq!(before.time(), -1.0);
+        assert_eq!(before.look_from(), Point3::new(-2.0, 1.0, 0.0));
+        assert_eq!(before.vfov(), 60.0);
+
+        let after = animation.at(5.0);
+        assert_eq!(after.look_from(), Point3::new(4.0, 0.0, 2.0));
+        assert_eq!(after.focus_dist(), 5.0);
+    }
+
+    #[test]
+    fn linear_midpoint() {
+        let animation = CameraAnimation::new(keyframes(), Interpolation::Linear);
+        let key = animation.at(2.0);
+
+        assert_eq!(key.look_from(), Point3::new(2.0, 0.0, 1.0));
+        assert_eq!(key.look_at(), Point3::new(0.5, 0.5, 1.0));
+        assert_eq!(key.vfov(), 30.0);
+        assert_eq!(key.focus_dist(), 3.0);
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_keyframes() {
+        let animation = CameraAnimation::new(keyframes(), Interpolation::CatmullRom);
+
+        for expected in keyframes() {
+            let key = animation.at(expected.time());
+
+            assert!((key.look_from() - expected.look_from()).length() < 1e-12);
+            assert!((key.look_at() - expected.look_at()).length() < 1e-12);
+            assert!((key.vfov() - expected.vfov()).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn slerp_turns_around_to_the_opposite_direction() {
+        let from = Point3::new(0.0, 0.0, 0.0);
+        let animation = CameraAnimation::new(
+            vec![
+                Keyframe::new(0.0, from, Point3::new(0.0, 0.0, 2.0), 40.0, 1.0),
+                Keyframe::new(1.0, from, Point3::new(0.0, 0.0, -2.0), 40.0, 1.0),
+            ],
+            Interpolation::Linear,
+        )
+        .slerp(true);
+
+        // half way the view is turned sideways around the up vector
+        let look_at = animation.at(0.5).look_at();
+        assert!((look_at.length() - 2.0).abs() < 1e-12);
+        assert!(look_at.y().abs() < 1e-12 && look_at.z().abs() < 1e-12);
+    }
+}
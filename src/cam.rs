@@ -1,9 +1,175 @@
+use crate::exposure::Exposure;
+use crate::hit::Hittable;
 use crate::ray::Ray;
-use crate::vec3::{Point3, Vec3};
+use crate::sampler::{Independent, Sampler};
+use crate::texture::{ImageTexture, Texture};
+use crate::vec3::{Color, Point3, Vec3};
+use std::f64::consts::PI;
 
+/// A camera generating the rays through the image
+///
+/// u and v are the image coordinates in [0, 1], running from the left and
+/// the bottom respectively.
+pub trait Camera: Send + Sync + std::fmt::Debug {
+    /// Returns the ray through (u, v), None where the camera does not see anything
+    fn get_ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<Ray>;
+
+    /// Returns the ray through (u, v) and the weight of the radiance along it
+    /// per color channel
+    fn sample_ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<(Ray, Color)> {
+        self.get_ray(u, v, sampler)
+            .map(|ray| (ray, Color::new(1.0, 1.0, 1.0)))
+    }
+
+    /// Returns how the radiance seen by the camera is exposed
+    fn exposure(&self) -> Exposure {
+        Exposure::default()
+    }
+
+    /// Project a point in the world to the image coordinates (u, v) and its depth
+    ///
+    /// The depth is the distance along the view direction for planar
+    /// projections, and the distance to the camera for spherical ones. Returns
+    /// None for points outside of the image or behind the camera, and for
+    /// cameras which can not project.
+    fn project(&self, _point: Point3) -> Option<(f64, f64, f64)> {
+        None
+    }
+}
+
+/// Returns the raster position, from the top left corner in pixels, of the
+/// image coordinates (u, v) as sampled by the renderer
+pub fn raster_position(u: f64, v: f64, width: usize, height: usize) -> (f64, f64) {
+    (
+        u * (width - 1) as f64,
+        height as f64 - v * (height - 1) as f64,
+    )
+}
+
+/// Returns the projection if the image coordinates (u, v) lie within the image
+fn within_image(u: f64, v: f64, depth: f64) -> Option<(f64, f64, f64)> {
+    if (0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v) {
+        Some((u, v, depth))
+    } else {
+        None
+    }
+}
+
+/// Returns the orthonormal basis (u, v, w) of a camera looking from look_from to
+/// look_at, with u pointing right, v up and w backwards
+pub fn basis(look_from: Point3, look_at: Point3, vup: Vec3) -> (Vec3, Vec3, Vec3) {
+    let w = (look_from - look_at).unit();
+    let u = vup.cross(w).unit();
+    let v = w.cross(u);
+
+    (u, v, w)
+}
+
+/// An aperture of arbitrary shape given by the brightness of an image
+///
+/// Points on the aperture are sampled proportional to the mean of the color
+/// channels of the image, which covers the square around the unit disk.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    cdf: Vec<f64>,
+}
+
+impl ApertureMask {
+    pub fn new(image: &ImageTexture) -> Self {
+        let (width, height) = (image.width(), image.height());
+        let mut cdf = Vec::with_capacity(width * height);
+        let mut sum = 0.0;
+
+        // rows from the top, as stored in the image
+        for j in 0..height {
+            for i in 0..width {
+                let u = (i as f64 + 0.5) / width as f64;
+                let v = 1.0 - (j as f64 + 0.5) / height as f64;
+                let (r, g, b) = image.value(u, v, Point3::new(0.0, 0.0, 0.0)).xyz();
+
+                sum += ((r + g + b) / 3.0).max(0.0);
+                cdf.push(sum);
+            }
+        }
+
+        Self { width, height, cdf }
+    }
+
+    /// Sample a point in [-1, 1] x [-1, 1]
+    fn sample(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let total = self.cdf.last().copied().unwrap_or(0.0);
+
+        if total <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+
+        let (u1, dv) = sampler.get_2d();
+        let index = self
+            .cdf
+            .partition_point(|&c| c <= u1 * total)
+            .min(self.cdf.len() - 1);
+
+        // reuse the position of u1 within the pixel's share of the cdf
+        let start = if index > 0 { self.cdf[index - 1] } else { 0.0 };
+        let du = ((u1 * total - start) / (self.cdf[index] - start)).clamp(0.0, 1.0);
+
+        let (i, j) = (index % self.width, index / self.width);
+        let x = (i as f64 + du) / self.width as f64;
+        let y = (j as f64 + dv) / self.height as f64;
+
+        Vec3::new(2.0 * x - 1.0, 1.0 - 2.0 * y, 0.0)
+    }
+}
+
+/// The shape of the aperture of a thin lens, and therefore of its bokeh
 #[derive(Clone, Debug, PartialEq)]
-pub struct Camera {
+pub enum ApertureShape {
+    Circular,
+    /// A regular polygon with one corner per blade, rotated counter-clockwise by
+    /// rotation degrees
+    Polygonal {
+        blades: u32,
+        rotation: f64,
+    },
+    Image(ApertureMask),
+}
+
+impl ApertureShape {
+    /// Sample a point on the aperture in units of the lens radius
+    fn sample(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        match self {
+            ApertureShape::Circular => Vec3::random_in_unit_disk(sampler),
+            ApertureShape::Polygonal { blades, rotation } => {
+                let blades = (*blades).max(3) as f64;
+                let (u1, u2) = sampler.get_2d();
+
+                // pick one of the triangles between the center and two corners
+                let k = (u1 * blades).floor().min(blades - 1.0);
+                let u1 = u1 * blades - k;
+
+                let corner = |k: f64| {
+                    let phi = rotation.to_radians() + 2.0 * PI * k / blades;
+                    Vec3::new(phi.cos(), phi.sin(), 0.0)
+                };
+
+                let s = u1.sqrt();
+                s * ((1.0 - u2) * corner(k) + u2 * corner(k + 1.0))
+            }
+            ApertureShape::Image(mask) => mask.sample(sampler),
+        }
+    }
+}
+
+/// A thin lens perspective camera
+#[derive(Clone, Debug, PartialEq)]
+pub struct PerspectiveCamera {
     lens_radius: f64,
+    aperture_shape: ApertureShape,
+    cat_eye: f64,
+    aspect_ratio: f64,
+    exposure: Exposure,
     origin: Point3,
     horizontal: Vec3,
     vertical: Vec3,
@@ -11,26 +177,93 @@ pub struct Camera {
     v: Vec3,
     w: Vec3,
     lower_left_corner: Point3,
+    focal_plane: Option<(Point3, Vec3)>,
 }
 
-impl Camera {
-    pub fn get_ray(&self, u: f64, v: f64) -> Ray {
-        let rd = self.lens_radius * Vec3::random_in_unit_disk();
+impl Camera for PerspectiveCamera {
+    fn get_ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let rd = self.aperture_shape.sample(sampler);
+
+        // the aperture is clipped by a disk moving outwards towards the edges of
+        // the frame, as a lens barrel would
+        if self.cat_eye > 0.0 {
+            let edge = Vec3::new((2.0 * u - 1.0) * self.aspect_ratio, 2.0 * v - 1.0, 0.0)
+                / (self.aspect_ratio * self.aspect_ratio + 1.0).sqrt();
+
+            if (rd - self.cat_eye * edge).length_squared() > 1.0 {
+                return None;
+            }
+        }
+
+        let rd = self.lens_radius * rd;
         let offset = self.u * rd.x() + self.v * rd.y();
+        let target = self.lower_left_corner + u * self.horizontal + v * self.vertical;
 
-        Ray::new(
+        // a tilted lens focuses where the ray through the lens center meets the
+        // tilted focal plane, or at infinity if it never does
+        let target = match self.focal_plane {
+            Some((point, normal)) => {
+                let direction = target - self.origin;
+                let t = (point - self.origin).dot(normal) / direction.dot(normal);
+
+                if !t.is_finite() || t <= 0.0 {
+                    return Some(Ray::new(self.origin + offset, direction));
+                }
+
+                self.origin + t * direction
+            }
+            None => target,
+        };
+
+        Some(Ray::new(
             self.origin + offset,
-            self.lower_left_corner + u * self.horizontal + v * self.vertical - self.origin - offset,
+            target - self.origin - offset,
+        ))
+    }
+
+    fn project(&self, point: Point3) -> Option<(f64, f64, f64)> {
+        let direction = point - self.origin;
+        let depth = -direction.dot(self.w);
+
+        if depth <= 0.0 {
+            return None;
+        }
+
+        // intersect the ray through the lens center with the image window
+        let focus_dist = (self.origin - self.lower_left_corner).dot(self.w);
+        let window = self.origin + focus_dist / depth * direction - self.lower_left_corner;
+
+        within_image(
+            window.dot(self.horizontal) / self.horizontal.length_squared(),
+            window.dot(self.vertical) / self.vertical.length_squared(),
+            depth,
         )
     }
+
+    fn exposure(&self) -> Exposure {
+        self.exposure
+    }
 }
 
+/// Builds a PerspectiveCamera
 #[derive(Clone, Debug, PartialEq)]
 pub struct CameraBuilder {
     vfov: f64,
     aspect_ratio: f64,
     aperture: f64,
+    aperture_shape: ApertureShape,
+    cat_eye: f64,
+    f_stop: Option<f64>,
+    shutter_speed: Option<f64>,
+    iso: f64,
+    white_balance: Option<f64>,
+    sensor_height: f64,
+    units_per_mm: f64,
     focus_dist: f64,
+    focus_point: Option<Point3>,
+    lens_shift: (f64, f64),
+    tilt: f64,
+    swing: f64,
     look_from: Point3,
     look_at: Point3,
     vup: Vec3,
@@ -42,7 +275,19 @@ impl Default for CameraBuilder {
             vfov: 90.0,
             aspect_ratio: 16.0 / 9.0,
             aperture: 1.0,
+            aperture_shape: ApertureShape::Circular,
+            cat_eye: 0.0,
+            f_stop: None,
+            shutter_speed: None,
+            iso: 100.0,
+            white_balance: None,
+            sensor_height: 24.0,
+            units_per_mm: 0.001,
             focus_dist: 1.0,
+            focus_point: None,
+            lens_shift: (0.0, 0.0),
+            tilt: 0.0,
+            swing: 0.0,
             look_from: Point3::new(0.0, 0.0, 0.0),
             look_at: Point3::new(0.0, 0.0, 1.0),
             vup: Point3::new(0.0, 1.0, 0.0),
@@ -66,8 +311,100 @@ impl CameraBuilder {
         self
     }
 
+    pub fn aperture_shape(&mut self, aperture_shape: ApertureShape) -> &mut Self {
+        self.aperture_shape = aperture_shape;
+        self
+    }
+
+    /// Sets how far the aperture is clipped into a cat's eye at the corners of
+    /// the frame, from 0.0 for not at all up to 2.0 for completely
+    pub fn cat_eye(&mut self, cat_eye: f64) -> &mut Self {
+        self.cat_eye = cat_eye;
+        self
+    }
+
+    /// Sets the aperture by its f-number instead of its diameter
+    ///
+    /// The focal length follows from the vertical field of view and the sensor
+    /// height.
+    pub fn f_stop(&mut self, f_stop: f64) -> &mut Self {
+        self.f_stop = Some(f_stop);
+        self
+    }
+
+    /// Sets the shutter speed in seconds, exposing the image photographically
+    ///
+    /// A pinhole camera, with an aperture of 0.0 and no f-number, is not
+    /// exposed photographically.
+    pub fn shutter_speed(&mut self, shutter_speed: f64) -> &mut Self {
+        self.shutter_speed = Some(shutter_speed);
+        self
+    }
+
+    /// Sets the ISO sensitivity of a photographic exposure, 100 by default
+    pub fn iso(&mut self, iso: f64) -> &mut Self {
+        self.iso = iso;
+        self
+    }
+
+    /// Sets the color temperature in Kelvin which appears white
+    pub fn white_balance(&mut self, kelvin: f64) -> &mut Self {
+        self.white_balance = Some(kelvin);
+        self
+    }
+
+    /// Sets the height of the sensor in mm, 24 for full frame
+    pub fn sensor_height(&mut self, sensor_height: f64) -> &mut Self {
+        self.sensor_height = sensor_height;
+        self
+    }
+
+    /// Sets the size of a millimeter on the sensor in world units
+    pub fn units_per_mm(&mut self, units_per_mm: f64) -> &mut Self {
+        self.units_per_mm = units_per_mm;
+        self
+    }
+
     pub fn focus_dist(&mut self, focus_dist: f64) -> &mut Self {
         self.focus_dist = focus_dist;
+        self.focus_point = None;
+        self
+    }
+
+    /// Focus on the plane through a point, instead of at focus_dist
+    ///
+    /// Points which are not in front of the camera are ignored, keeping focus_dist.
+    pub fn focus_on(&mut self, point: Point3) -> &mut Self {
+        self.focus_point = Some(point);
+        self
+    }
+
+    /// Shift the image window off the view axis, in fractions of the image
+    /// width and height
+    ///
+    /// Shifting up instead of tilting the view keeps vertical lines parallel.
+    pub fn lens_shift(&mut self, x: f64, y: f64) -> &mut Self {
+        self.lens_shift = (x, y);
+        self
+    }
+
+    /// Tilt the plane of focus around the horizontal axis through the focus
+    /// distance, in degrees
+    ///
+    /// Positive angles move the plane towards the camera at the bottom of the
+    /// image, e.g. to keep the ground in focus.
+    pub fn tilt(&mut self, tilt: f64) -> &mut Self {
+        self.tilt = tilt;
+        self
+    }
+
+    /// Swing the plane of focus around the vertical axis through the focus
+    /// distance, in degrees
+    ///
+    /// Positive angles move the plane away from the camera at the right of
+    /// the image.
+    pub fn swing(&mut self, swing: f64) -> &mut Self {
+        self.swing = swing;
         self
     }
 
@@ -86,32 +423,993 @@ impl CameraBuilder {
         self
     }
 
-    pub fn build(&self) -> Camera {
+    /// Returns the distance from the camera to the plane in focus
+    fn focus_distance(&self) -> f64 {
+        let (_, _, w) = basis(self.look_from, self.look_at, self.vup);
+
+        match self
+            .focus_point
+            .map(|point| (self.look_from - point).dot(w))
+        {
+            Some(distance) if distance > 0.0 => distance,
+            _ => self.focus_dist,
+        }
+    }
+
+    /// Returns the focal length in mm of a lens with the field of view on the sensor
+    fn focal_length(&self) -> f64 {
+        self.sensor_height / (2.0 * (self.vfov.to_radians() / 2.0).tan())
+    }
+
+    /// Returns the aperture diameter in world units
+    fn aperture_diameter(&self) -> f64 {
+        match self.f_stop {
+            Some(f_stop) => self.focal_length() / f_stop * self.units_per_mm,
+            None => self.aperture,
+        }
+    }
+
+    fn exposure(&self) -> Exposure {
+        // a pinhole has no f-number to expose with
+        let f_stop = self.f_stop.or_else(|| {
+            Some(self.focal_length() * self.units_per_mm / self.aperture)
+                .filter(|_| self.aperture > 0.0)
+        });
+
+        let exposure = match (self.shutter_speed, f_stop) {
+            (Some(shutter_speed), Some(f_stop)) => {
+                Exposure::photographic(f_stop, shutter_speed, self.iso)
+            }
+            _ => Exposure::default(),
+        };
+
+        match self.white_balance {
+            Some(kelvin) => exposure.white_balance(kelvin),
+            None => exposure,
+        }
+    }
+
+    pub fn build(&self) -> PerspectiveCamera {
         let theta = self.vfov.to_radians();
         let h = (theta / 2.0).tan();
 
         let viewport_height = 2.0 * h;
         let viewport_width = self.aspect_ratio * viewport_height;
 
-        let w = (self.look_from - self.look_at).unit();
-        let u = self.vup.cross(w).unit();
-        let v = w.cross(u);
+        let (u, v, w) = basis(self.look_from, self.look_at, self.vup);
+
+        let focus_dist = self.focus_distance();
+        let horizontal = focus_dist * viewport_width * u;
+        let vertical = focus_dist * viewport_height * v;
+        let (shift_x, shift_y) = self.lens_shift;
 
-        let horizontal = self.focus_dist * viewport_width * u;
-        let vertical = self.focus_dist * viewport_height * v;
+        let focal_plane = if self.tilt != 0.0 || self.swing != 0.0 {
+            let normal = w + self.tilt.to_radians().tan() * v + self.swing.to_radians().tan() * u;
+            Some((self.look_from - focus_dist * w, normal.unit()))
+        } else {
+            None
+        };
 
-        Camera {
-            lens_radius: self.aperture / 2.0,
+        PerspectiveCamera {
+            lens_radius: self.aperture_diameter() / 2.0,
+            aperture_shape: self.aperture_shape.clone(),
+            cat_eye: self.cat_eye,
+            aspect_ratio: self.aspect_ratio,
+            exposure: self.exposure(),
             origin: self.look_from,
             horizontal,
             vertical,
             u,
             v,
             w,
-            lower_left_corner: self.look_from
-                - horizontal / 2.0
-                - vertical / 2.0
-                - self.focus_dist * w,
+            lower_left_corner: self.look_from - horizontal / 2.0 - vertical / 2.0 - focus_dist * w
+                + shift_x * horizontal
+                + shift_y * vertical,
+            focal_plane,
+        }
+    }
+
+    /// Build the camera focused on whatever the ray through the center of the
+    /// image hits first
+    ///
+    /// The center ray is the one of a pinhole camera, so it follows the lens
+    /// shift. Keeps the focus if the center ray does not hit anything.
+    pub fn build_focused<H: Hittable>(&self, world: H) -> PerspectiveCamera {
+        let mut pinhole = self.clone();
+        pinhole.aperture = 0.0;
+        pinhole.f_stop = None;
+
+        let center_ray = pinhole.build().get_ray(0.5, 0.5, &mut Independent::new(0));
+
+        match center_ray.and_then(|ray| world.hit(&ray, 0.001, f64::INFINITY)) {
+            Some(hr) => self.clone().focus_on(hr.point()).build(),
+            None => self.build(),
+        }
+    }
+}
+
+/// A camera with parallel rays, keeping parallel lines parallel as in
+/// architectural elevations
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrthographicCamera {
+    direction: Vec3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    lower_left_corner: Point3,
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, u: f64, v: f64, _sampler: &mut dyn Sampler) -> Option<Ray> {
+        Some(Ray::new(
+            self.lower_left_corner + u * self.horizontal + v * self.vertical,
+            self.direction,
+        ))
+    }
+
+    fn project(&self, point: Point3) -> Option<(f64, f64, f64)> {
+        let offset = point - self.lower_left_corner;
+        let depth = offset.dot(self.direction);
+
+        if depth < 0.0 {
+            return None;
+        }
+
+        within_image(
+            offset.dot(self.horizontal) / self.horizontal.length_squared(),
+            offset.dot(self.vertical) / self.vertical.length_squared(),
+            depth,
+        )
+    }
+}
+
+/// Builds an OrthographicCamera
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrthographicCameraBuilder {
+    view_height: f64,
+    aspect_ratio: f64,
+    look_from: Point3,
+    look_at: Point3,
+    vup: Vec3,
+}
+
+impl Default for OrthographicCameraBuilder {
+    fn default() -> Self {
+        Self {
+            view_height: 2.0,
+            aspect_ratio: 16.0 / 9.0,
+            look_from: Point3::new(0.0, 0.0, 0.0),
+            look_at: Point3::new(0.0, 0.0, 1.0),
+            vup: Point3::new(0.0, 1.0, 0.0),
+        }
+    }
+}
+
+impl OrthographicCameraBuilder {
+    /// Sets the height of the visible area in world units
+    pub fn view_height(&mut self, view_height: f64) -> &mut Self {
+        self.view_height = view_height;
+        self
+    }
+
+    pub fn aspect_ratio(&mut self, aspect_ratio: f64) -> &mut Self {
+        self.aspect_ratio = aspect_ratio;
+        self
+    }
+
+    pub fn look_from(&mut self, look_from: Point3) -> &mut Self {
+        self.look_from = look_from;
+        self
+    }
+
+    pub fn look_at(&mut self, look_at: Point3) -> &mut Self {
+        self.look_at = look_at;
+        self
+    }
+
+    pub fn view_up(&mut self, view_up: Vec3) -> &mut Self {
+        self.vup = view_up;
+        self
+    }
+
+    pub fn build(&self) -> OrthographicCamera {
+        let (u, v, w) = basis(self.look_from, self.look_at, self.vup);
+
+        let horizontal = self.aspect_ratio * self.view_height * u;
+        let vertical = self.view_height * v;
+
+        OrthographicCamera {
+            direction: -w,
+            horizontal,
+            vertical,
+            lower_left_corner: self.look_from - horizontal / 2.0 - vertical / 2.0,
+        }
+    }
+}
+
+/// How a fisheye lens maps the angle to the optical axis to the image circle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FisheyeProjection {
+    /// The distance from the center is proportional to the angle
+    Equidistant,
+    /// The area in the image is proportional to the solid angle
+    Equisolid,
+}
+
+/// A circular fisheye camera, whose image circle fills the image height
+///
+/// Nothing is seen outside of the image circle.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FisheyeCamera {
+    projection: FisheyeProjection,
+    max_theta: f64,
+    aspect_ratio: f64,
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, u: f64, v: f64, _sampler: &mut dyn Sampler) -> Option<Ray> {
+        let x = (2.0 * u - 1.0) * self.aspect_ratio;
+        let y = 2.0 * v - 1.0;
+        let r = (x * x + y * y).sqrt();
+
+        if r > 1.0 {
+            return None;
+        }
+
+        let theta = match self.projection {
+            FisheyeProjection::Equidistant => r * self.max_theta,
+            FisheyeProjection::Equisolid => {
+                2.0 * (r * (self.max_theta / 2.0).sin()).clamp(-1.0, 1.0).asin()
+            }
+        };
+        let phi = y.atan2(x);
+
+        let direction =
+            theta.sin() * (phi.cos() * self.u + phi.sin() * self.v) - theta.cos() * self.w;
+
+        Some(Ray::new(self.origin, direction))
+    }
+
+    fn project(&self, point: Point3) -> Option<(f64, f64, f64)> {
+        let direction = point - self.origin;
+        let distance = direction.length();
+        let (x, y) = (direction.dot(self.u), direction.dot(self.v));
+
+        let theta = (-direction.dot(self.w) / distance).clamp(-1.0, 1.0).acos();
+        let r = match self.projection {
+            FisheyeProjection::Equidistant => theta / self.max_theta,
+            FisheyeProjection::Equisolid => (theta / 2.0).sin() / (self.max_theta / 2.0).sin(),
+        };
+
+        if !(0.0..=1.0).contains(&r) {
+            return None;
+        }
+
+        let phi = y.atan2(x);
+
+        Some((
+            (r * phi.cos() / self.aspect_ratio + 1.0) / 2.0,
+            (r * phi.sin() + 1.0) / 2.0,
+            distance,
+        ))
+    }
+}
+
+/// Builds a FisheyeCamera
+#[derive(Clone, Debug, PartialEq)]
+pub struct FisheyeCameraBuilder {
+    projection: FisheyeProjection,
+    fov: f64,
+    aspect_ratio: f64,
+    look_from: Point3,
+    look_at: Point3,
+    vup: Vec3,
+}
+
+impl Default for FisheyeCameraBuilder {
+    fn default() -> Self {
+        Self {
+            projection: FisheyeProjection::Equidistant,
+            fov: 180.0,
+            aspect_ratio: 16.0 / 9.0,
+            look_from: Point3::new(0.0, 0.0, 0.0),
+            look_at: Point3::new(0.0, 0.0, 1.0),
+            vup: Point3::new(0.0, 1.0, 0.0),
+        }
+    }
+}
+
+impl FisheyeCameraBuilder {
+    pub fn projection(&mut self, projection: FisheyeProjection) -> &mut Self {
+        self.projection = projection;
+        self
+    }
+
+    /// Sets the field of view across the image circle in degrees, up to 360
+    pub fn fov(&mut self, fov: f64) -> &mut Self {
+        self.fov = fov;
+        self
+    }
+
+    pub fn aspect_ratio(&mut self, aspect_ratio: f64) -> &mut Self {
+        self.aspect_ratio = aspect_ratio;
+        self
+    }
+
+    pub fn look_from(&mut self, look_from: Point3) -> &mut Self {
+        self.look_from = look_from;
+        self
+    }
+
+    pub fn look_at(&mut self, look_at: Point3) -> &mut Self {
+        self.look_at = look_at;
+        self
+    }
+
+    pub fn view_up(&mut self, view_up: Vec3) -> &mut Self {
+        self.vup = view_up;
+        self
+    }
+
+    pub fn build(&self) -> FisheyeCamera {
+        let (u, v, w) = basis(self.look_from, self.look_at, self.vup);
+
+        FisheyeCamera {
+            projection: self.projection,
+            max_theta: self.fov.clamp(0.0, 360.0).to_radians() / 2.0,
+            aspect_ratio: self.aspect_ratio,
+            origin: self.look_from,
+            u,
+            v,
+            w,
+        }
+    }
+}
+
+/// A 360 degree panorama camera in equirectangular projection
+///
+/// The longitude runs over the image width, centered on the view direction,
+/// and the latitude over the image height. Images should have an aspect ratio
+/// of 2:1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EquirectangularCamera {
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, u: f64, v: f64, _sampler: &mut dyn Sampler) -> Option<Ray> {
+        let longitude = (u - 0.5) * 2.0 * PI;
+        let latitude = (v - 0.5) * PI;
+
+        let direction = latitude.cos() * (longitude.sin() * self.u - longitude.cos() * self.w)
+            + latitude.sin() * self.v;
+
+        Some(Ray::new(self.origin, direction))
+    }
+
+    fn project(&self, point: Point3) -> Option<(f64, f64, f64)> {
+        let direction = point - self.origin;
+        let distance = direction.length();
+
+        if distance == 0.0 {
+            return None;
+        }
+
+        let longitude = direction.dot(self.u).atan2(-direction.dot(self.w));
+        let latitude = (direction.dot(self.v) / distance).clamp(-1.0, 1.0).asin();
+
+        Some((longitude / (2.0 * PI) + 0.5, latitude / PI + 0.5, distance))
+    }
+}
+
+/// Builds an EquirectangularCamera
+#[derive(Clone, Debug, PartialEq)]
+pub struct EquirectangularCameraBuilder {
+    look_from: Point3,
+    look_at: Point3,
+    vup: Vec3,
+}
+
+impl Default for EquirectangularCameraBuilder {
+    fn default() -> Self {
+        Self {
+            look_from: Point3::new(0.0, 0.0, 0.0),
+            look_at: Point3::new(0.0, 0.0, 1.0),
+            vup: Point3::new(0.0, 1.0, 0.0),
+        }
+    }
+}
+
+impl EquirectangularCameraBuilder {
+    pub fn look_from(&mut self, look_from: Point3) -> &mut Self {
+        self.look_from = look_from;
+        self
+    }
+
+    pub fn look_at(&mut self, look_at: Point3) -> &mut Self {
+        self.look_at = look_at;
+        self
+    }
+
+    pub fn view_up(&mut self, view_up: Vec3) -> &mut Self {
+        self.vup = view_up;
+        self
+    }
+
+    pub fn build(&self) -> EquirectangularCamera {
+        let (u, v, w) = basis(self.look_from, self.look_at, self.vup);
+
+        EquirectangularCamera {
+            origin: self.look_from,
+            u,
+            v,
+            w,
+        }
+    }
+}
+
+/// How the two eyes of a stereo camera are arranged in the image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoLayout {
+    /// The left eye in the left half, the right eye in the right half
+    SideBySide,
+    /// The left eye in the top half, the right eye in the bottom half
+    OverUnder,
+}
+
+/// How the views of the two eyes of a stereo camera converge
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Convergence {
+    /// Both eyes are rotated towards the convergence point
+    ToeIn,
+    /// Both eyes look parallel with their image windows shifted to coincide at
+    /// the convergence distance, avoiding vertical parallax
+    OffAxis,
+}
+
+/// A stereo camera rendering both eyes into one image
+///
+/// Points are projected into the half of the image of the left eye only.
+#[derive(Debug)]
+pub struct StereoCamera {
+    layout: StereoLayout,
+    left: Box<dyn Camera>,
+    right: Box<dyn Camera>,
+}
+
+impl Camera for StereoCamera {
+    fn get_ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        self.sample_ray(u, v, sampler).map(|(ray, _)| ray)
+    }
+
+    fn sample_ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<(Ray, Color)> {
+        match self.layout {
+            StereoLayout::SideBySide if u < 0.5 => self.left.sample_ray(2.0 * u, v, sampler),
+            StereoLayout::SideBySide => self.right.sample_ray(2.0 * u - 1.0, v, sampler),
+            StereoLayout::OverUnder if v >= 0.5 => self.left.sample_ray(u, 2.0 * v - 1.0, sampler),
+            StereoLayout::OverUnder => self.right.sample_ray(u, 2.0 * v, sampler),
+        }
+    }
+
+    fn exposure(&self) -> Exposure {
+        self.left.exposure()
+    }
+
+    /// Projects into the image of the left eye
+    fn project(&self, point: Point3) -> Option<(f64, f64, f64)> {
+        let (u, v, depth) = self.left.project(point)?;
+
+        match self.layout {
+            StereoLayout::SideBySide => Some((u / 2.0, v, depth)),
+            StereoLayout::OverUnder => Some((u, (v + 1.0) / 2.0, depth)),
+        }
+    }
+}
+
+/// One eye of an omnidirectional stereo panorama
+///
+/// Like an EquirectangularCamera, but every ray starts offset from the center
+/// perpendicular to its horizontal direction, as if the eyes turned around
+/// the center to look in that direction. As the rays do not meet in a single
+/// point, points are not projected.
+#[derive(Clone, Debug, PartialEq)]
+struct OmnidirectionalEye {
+    camera: EquirectangularCamera,
+    offset: f64,
+}
+
+impl Camera for OmnidirectionalEye {
+    fn get_ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        let ray = self.camera.get_ray(u, v, sampler)?;
+
+        let longitude = (u - 0.5) * 2.0 * PI;
+        let right = longitude.cos() * self.camera.u + longitude.sin() * self.camera.w;
+
+        Some(Ray::new(
+            ray.origin() + self.offset * right,
+            ray.direction(),
+        ))
+    }
+}
+
+/// Builds a StereoCamera from the CameraBuilder of the center between the eyes
+///
+/// The aspect ratio of the CameraBuilder is the one of each eye's half of the
+/// image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StereoCameraBuilder {
+    camera: CameraBuilder,
+    interocular_distance: f64,
+    convergence: Convergence,
+    convergence_dist: Option<f64>,
+    layout: StereoLayout,
+    omnidirectional: bool,
+}
+
+impl Default for StereoCameraBuilder {
+    fn default() -> Self {
+        Self {
+            camera: CameraBuilder::default(),
+            interocular_distance: 0.065,
+            convergence: Convergence::OffAxis,
+            convergence_dist: None,
+            layout: StereoLayout::SideBySide,
+            omnidirectional: false,
+        }
+    }
+}
+
+impl StereoCameraBuilder {
+    pub fn camera(&mut self, camera: &CameraBuilder) -> &mut Self {
+        self.camera = camera.clone();
+        self
+    }
+
+    pub fn interocular_distance(&mut self, interocular_distance: f64) -> &mut Self {
+        self.interocular_distance = interocular_distance;
+        self
+    }
+
+    pub fn convergence(&mut self, convergence: Convergence) -> &mut Self {
+        self.convergence = convergence;
+        self
+    }
+
+    /// Sets the distance at which the eyes converge, the focus distance by default
+    pub fn convergence_dist(&mut self, convergence_dist: f64) -> &mut Self {
+        self.convergence_dist = Some(convergence_dist);
+        self
+    }
+
+    pub fn layout(&mut self, layout: StereoLayout) -> &mut Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Render an omnidirectional stereo 360 panorama for each eye instead
+    ///
+    /// Only the view of the CameraBuilder is used, the eyes converge at infinity.
+    pub fn omnidirectional(&mut self, omnidirectional: bool) -> &mut Self {
+        self.omnidirectional = omnidirectional;
+        self
+    }
+
+    pub fn build(&self) -> StereoCamera {
+        let (left, right): (Box<dyn Camera>, Box<dyn Camera>) = if self.omnidirectional {
+            let camera = EquirectangularCameraBuilder::default()
+                .look_from(self.camera.look_from)
+                .look_at(self.camera.look_at)
+                .view_up(self.camera.vup)
+                .build();
+            let eye = |offset| OmnidirectionalEye {
+                camera: camera.clone(),
+                offset,
+            };
+
+            (
+                Box::new(eye(-self.interocular_distance / 2.0)),
+                Box::new(eye(self.interocular_distance / 2.0)),
+            )
+        } else {
+            (
+                Box::new(self.eye(-self.interocular_distance / 2.0)),
+                Box::new(self.eye(self.interocular_distance / 2.0)),
+            )
+        };
+
+        StereoCamera {
+            layout: self.layout,
+            left,
+            right,
+        }
+    }
+
+    /// Build the perspective camera of the eye offset to the right by offset
+    fn eye(&self, offset: f64) -> PerspectiveCamera {
+        let camera = &self.camera;
+        let convergence_dist = self
+            .convergence_dist
+            .unwrap_or_else(|| camera.focus_distance());
+
+        let (u, _, w) = basis(camera.look_from, camera.look_at, camera.vup);
+        let shift = offset * u;
+
+        match self.convergence {
+            Convergence::ToeIn => camera
+                .clone()
+                .look_from(camera.look_from + shift)
+                .look_at(camera.look_from - convergence_dist * w)
+                .build(),
+            Convergence::OffAxis => {
+                let mut eye = camera
+                    .clone()
+                    .look_from(camera.look_from + shift)
+                    .look_at(camera.look_at + shift)
+                    .build();
+
+                // move the image window back towards the center line, so the
+                // windows of both eyes meet at the convergence distance
+                eye.lower_left_corner -= camera.focus_distance() / convergence_dist * shift;
+                eye
+            }
+        }
+    }
+}
+
+/// Adds the imperfections of real lenses to another camera
+///
+/// Image positions are measured from the center of the image in units of
+/// half the image height. All imperfections are off by default.
+#[derive(Debug)]
+pub struct LensImperfections {
+    camera: Box<dyn Camera>,
+    aspect_ratio: f64,
+    radial: (f64, f64, f64),
+    tangential: (f64, f64),
+    chromatic_aberration: f64,
+    vignetting: Option<f64>,
+}
+
+impl LensImperfections {
+    pub fn new(camera: Box<dyn Camera>, aspect_ratio: f64) -> Self {
+        Self {
+            camera,
+            aspect_ratio,
+            radial: (0.0, 0.0, 0.0),
+            tangential: (0.0, 0.0),
+            chromatic_aberration: 0.0,
+            vignetting: None,
+        }
+    }
+
+    /// Sets the Brown-Conrady distortion, mapping undistorted to distorted
+    /// image positions as in common lens calibration tools
+    ///
+    /// Negative k1 give barrel, positive k1 pincushion distortion. Barrel
+    /// distortion strong enough to fold the image over within the frame, e.g.
+    /// k1 = -0.05 in the corners of a 16:9 image, leaves the corners black, as
+    /// no direction is imaged there.
+    pub fn distortion(mut self, k1: f64, k2: f64, k3: f64, p1: f64, p2: f64) -> Self {
+        self.radial = (k1, k2, k3);
+        self.tangential = (p1, p2);
+        self
+    }
+
+    /// Sets the lateral chromatic aberration, by how much larger the blue and
+    /// smaller the red channel is imaged than the green one
+    pub fn chromatic_aberration(mut self, amount: f64) -> Self {
+        self.chromatic_aberration = amount;
+        self
+    }
+
+    /// Darken the image towards its edges with the cos⁴ falloff of a lens with
+    /// the given vertical field of view in degrees
+    pub fn vignetting(mut self, vfov: f64) -> Self {
+        self.vignetting = Some(vfov);
+        self
+    }
+
+    /// Returns the distorted position of an undistorted one
+    fn distort(&self, x: f64, y: f64) -> (f64, f64) {
+        let (k1, k2, k3) = self.radial;
+        let (p1, p2) = self.tangential;
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+
+        (
+            x * radial + 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x),
+            y * radial + p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y,
+        )
+    }
+
+    /// Returns the Jacobian of distort as the rows (dx/dx, dx/dy) and (dy/dx, dy/dy)
+    fn distortion_jacobian(&self, x: f64, y: f64) -> ((f64, f64), (f64, f64)) {
+        let (k1, k2, k3) = self.radial;
+        let (p1, p2) = self.tangential;
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+        let d_radial = k1 + r2 * (2.0 * k2 + r2 * 3.0 * k3);
+
+        (
+            (
+                radial + 2.0 * x * x * d_radial + 2.0 * p1 * y + 6.0 * p2 * x,
+                2.0 * x * y * d_radial + 2.0 * p1 * x + 2.0 * p2 * y,
+            ),
+            (
+                2.0 * x * y * d_radial + 2.0 * p1 * x + 2.0 * p2 * y,
+                radial + 2.0 * y * y * d_radial + 6.0 * p1 * y + 2.0 * p2 * x,
+            ),
+        )
+    }
+
+    /// Returns the undistorted position of a distorted one by Newton iteration
+    ///
+    /// Returns None if there is none, as beyond the edge of strong barrel
+    /// distortion where the image folds over.
+    fn undistort(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let (mut ux, mut uy) = (x, y);
+
+        for _ in 0..50 {
+            let (dx, dy) = self.distort(ux, uy);
+            let ((a, b), (c, d)) = self.distortion_jacobian(ux, uy);
+            let det = a * d - b * c;
+
+            // only the part of the image up to the fold is imaged
+            if det.is_nan() || det <= 0.0 {
+                return None;
+            }
+
+            let (ex, ey) = (x - dx, y - dy);
+            ux += (d * ex - b * ey) / det;
+            uy += (a * ey - c * ex) / det;
+
+            if ex.abs() + ey.abs() <= 1e-12 * (1.0 + x.abs() + y.abs()) {
+                break;
+            }
+        }
+
+        let (dx, dy) = self.distort(ux, uy);
+        if (dx - x).abs() + (dy - y).abs() > 1e-9 * (1.0 + x.abs() + y.abs()) {
+            return None;
+        }
+
+        Some((ux, uy))
+    }
+}
+
+impl Camera for LensImperfections {
+    fn get_ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        self.sample_ray(u, v, sampler).map(|(ray, _)| ray)
+    }
+
+    fn sample_ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<(Ray, Color)> {
+        let (mut x, mut y) = ((2.0 * u - 1.0) * self.aspect_ratio, 2.0 * v - 1.0);
+        let mut weight = Color::new(1.0, 1.0, 1.0);
+
+        // each sample sees a single channel, imaged at its own scale
+        if self.chromatic_aberration != 0.0 {
+            let channel = ((sampler.get_1d() * 3.0) as usize).min(2);
+            let scale = 1.0 + self.chromatic_aberration * (channel as f64 - 1.0);
+
+            x /= scale;
+            y /= scale;
+            weight = match channel {
+                0 => Color::new(3.0, 0.0, 0.0),
+                1 => Color::new(0.0, 3.0, 0.0),
+                _ => Color::new(0.0, 0.0, 3.0),
+            };
+        }
+
+        let (x, y) = if self.radial != (0.0, 0.0, 0.0) || self.tangential != (0.0, 0.0) {
+            self.undistort(x, y)?
+        } else {
+            (x, y)
+        };
+
+        if let Some(vfov) = self.vignetting {
+            let tan2 = (x * x + y * y) * (vfov.to_radians() / 2.0).tan().powi(2);
+            weight /= (1.0 + tan2) * (1.0 + tan2);
+        }
+
+        let (ray, camera_weight) = self.camera.sample_ray(
+            (x / self.aspect_ratio + 1.0) / 2.0,
+            (y + 1.0) / 2.0,
+            sampler,
+        )?;
+
+        Some((ray, weight * camera_weight))
+    }
+
+    fn exposure(&self) -> Exposure {
+        self.camera.exposure()
+    }
+
+    /// Projects to where the green channel images the point
+    ///
+    /// Points outside of the image of the wrapped camera are not projected,
+    /// even if distortion moves them into the image.
+    fn project(&self, point: Point3) -> Option<(f64, f64, f64)> {
+        let (u, v, depth) = self.camera.project(point)?;
+        let (x, y) = self.distort((2.0 * u - 1.0) * self.aspect_ratio, 2.0 * v - 1.0);
+
+        within_image((x / self.aspect_ratio + 1.0) / 2.0, (y + 1.0) / 2.0, depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Image positions inside the image circle of a fisheye camera
+    const POSITIONS: [(f64, f64); 4] = [(0.5, 0.5), (0.4, 0.3), (0.6, 0.8), (0.3, 0.5)];
+
+    /// Assert that points along the rays through the image project back onto
+    /// their image positions
+    fn assert_round_trip(camera: &dyn Camera, ts: &[f64]) {
+        let mut sampler = Independent::new(0);
+
+        for &(u, v) in &POSITIONS {
+            let ray = camera.get_ray(u, v, &mut sampler).expect("ray");
+
+            for &t in ts {
+                let (pu, pv, depth) = camera.project(ray.at(t)).expect("projection");
+
+                assert!(
+                    (pu - u).abs() < 1e-6 && (pv - v).abs() < 1e-6 && depth > 0.0,
+                    "({}, {}) at t = {} projects to ({}, {})",
+                    u,
+                    v,
+                    t,
+                    pu,
+                    pv
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn perspective_round_trip() {
+        let mut builder = CameraBuilder::default();
+        builder
+            .look_from(Point3::new(1.0, 2.0, 3.0))
+            .look_at(Point3::new(0.0, 0.0, 0.0))
+            .focus_dist(2.0)
+            .lens_shift(0.1, -0.2)
+            .tilt(10.0)
+            .swing(-5.0);
+
+        assert_round_trip(&builder.clone().aperture(0.0).build(), &[0.5, 1.0, 4.0]);
+        // rays from all over the lens pass the same point in focus
+        assert_round_trip(&builder.aperture(0.5).build(), &[1.0]);
+    }
+
+    #[test]
+    fn orthographic_round_trip() {
+        let camera = OrthographicCameraBuilder::default()
+            .look_from(Point3::new(1.0, 2.0, 3.0))
+            .look_at(Point3::new(0.0, 0.0, 0.0))
+            .view_height(3.0)
+            .build();
+
+        assert_round_trip(&camera, &[0.5, 1.0, 10.0]);
+    }
+
+    #[test]
+    fn fisheye_round_trip() {
+        for &projection in &[FisheyeProjection::Equidistant, FisheyeProjection::Equisolid] {
+            let camera = FisheyeCameraBuilder::default()
+                .projection(projection)
+                .fov(220.0)
+                .look_from(Point3::new(1.0, 2.0, 3.0))
+                .build();
+
+            assert_round_trip(&camera, &[0.5, 10.0]);
+        }
+    }
+
+    #[test]
+    fn equirectangular_round_trip() {
+        let camera = EquirectangularCameraBuilder::default()
+            .look_from(Point3::new(1.0, 2.0, 3.0))
+            .look_at(Point3::new(0.0, 0.0, 0.0))
+            .build();
+
+        assert_round_trip(&camera, &[0.5, 10.0]);
+    }
+
+    #[test]
+    fn lens_imperfections_round_trip() {
+        let mut builder = CameraBuilder::default();
+        builder.aperture(0.0).vertical_fov(60.0);
+        let camera = LensImperfections::new(Box::new(builder.build()), 16.0 / 9.0)
+            .distortion(-0.05, 0.01, 0.0, 0.001, -0.002)
+            .vignetting(60.0);
+
+        assert_round_trip(&camera, &[0.5, 10.0]);
+    }
+
+    #[test]
+    fn undistort_inverts_distort_in_the_corners() {
+        for &aspect_ratio in &[1.5, 16.0 / 9.0] {
+            for &k1 in &[0.1, -0.03] {
+                let camera = LensImperfections::new(
+                    Box::new(CameraBuilder::default().build()),
+                    aspect_ratio,
+                )
+                .distortion(k1, 0.0, 0.0, 0.002, -0.001);
+
+                for &(x, y) in &[
+                    (aspect_ratio, 1.0),
+                    (-aspect_ratio, -1.0),
+                    (aspect_ratio, -1.0),
+                ] {
+                    let (ux, uy) = camera.undistort(x, y).expect("undistorted position");
+                    let (dx, dy) = camera.distort(ux, uy);
+
+                    assert!(
+                        (dx - x).abs() < 1e-9 && (dy - y).abs() < 1e-9,
+                        "k1 = {} at ({}, {})",
+                        k1,
+                        x,
+                        y
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn undistort_stops_at_the_fold_of_barrel_distortion() {
+        let camera = LensImperfections::new(Box::new(CameraBuilder::default().build()), 16.0 / 9.0)
+            .distortion(-0.05, 0.0, 0.0, 0.0, 0.0);
+
+        assert_eq!(camera.undistort(16.0 / 9.0, 1.0), None);
+        assert!(camera.undistort(1.0, 0.5).is_some());
+    }
+
+    #[test]
+    fn pinhole_ignores_shutter_speed() {
+        let mut builder = CameraBuilder::default();
+        builder.aperture(0.0).shutter_speed(1.0 / 100.0);
+
+        assert_eq!(builder.build().exposure(), Exposure::default());
+        assert!(builder.f_stop(16.0).build().exposure().scale() < 1.0);
+    }
+
+    #[test]
+    fn stereo_keeps_the_weights_of_the_eyes() {
+        let eye = || {
+            let camera = CameraBuilder::default().aperture(0.0).build();
+            Box::new(LensImperfections::new(Box::new(camera), 16.0 / 9.0).vignetting(90.0))
+        };
+        let camera = StereoCamera {
+            layout: StereoLayout::SideBySide,
+            left: eye(),
+            right: eye(),
+        };
+        let mut sampler = Independent::new(0);
+
+        for &u in &[0.0, 1.0] {
+            let (_, weight) = camera.sample_ray(u, 0.0, &mut sampler).expect("ray");
+            assert!(weight.x() < 1.0);
+        }
+    }
+
+    #[test]
+    fn project_outside_of_image() {
+        let perspective = CameraBuilder::default().build();
+        let orthographic = OrthographicCameraBuilder::default().build();
+
+        for camera in [&perspective as &dyn Camera, &orthographic] {
+            assert_eq!(camera.project(Point3::new(100.0, 0.0, 1.0)), None);
+            assert_eq!(camera.project(Point3::new(0.0, 0.0, -1.0)), None);
+            assert!(camera.project(Point3::new(0.0, 0.0, 1.0)).is_some());
         }
     }
 }
@@ -0,0 +1,80 @@
+use crate::vec3::Color;
+
+/// The white point white balancing is relative to, in Kelvin
+const REFERENCE_WHITE: f64 = 6500.0;
+
+/// Maps the radiance reaching the camera to the brightness of the image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Exposure {
+    scale: f64,
+    white_balance: Color,
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+impl Exposure {
+    /// Create a new Exposure scaling the radiance by scale
+    pub fn new(scale: f64) -> Self {
+        Self {
+            scale,
+            white_balance: Color::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Create the exposure of a camera with the given f-number, shutter speed in
+    /// seconds and ISO sensitivity
+    ///
+    /// Uses the saturation based sensitivity, so a luminance of 1.0 / scale
+    /// just saturates the image. E.g. sunlit scenes of several thousand cd/m²
+    /// render at sane brightness at f/16, 1/100s and ISO 100.
+    pub fn photographic(f_stop: f64, shutter_speed: f64, iso: f64) -> Self {
+        Self::new(shutter_speed * iso / (100.0 * 1.2 * f_stop * f_stop))
+    }
+
+    /// Balance the image so that the light of a black body at kelvin appears white
+    pub fn white_balance(mut self, kelvin: f64) -> Self {
+        let (r, g, b) = Color::blackbody(kelvin).map(|c| c.max(1e-3)).xyz();
+        let (r_ref, g_ref, b_ref) = Color::blackbody(REFERENCE_WHITE).xyz();
+
+        // keep the brightness of the green channel
+        let gain = Color::new(r_ref / r, g_ref / g, b_ref / b);
+        self.white_balance = gain / gain.y();
+        self
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// Returns the exposed color
+    pub fn apply(&self, color: Color) -> Color {
+        self.scale * self.white_balance * color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn photographic_scale() {
+        // sunny 16, saturating at about 30000 cd/m²
+        let exposure = Exposure::photographic(16.0, 1.0 / 100.0, 100.0);
+
+        assert!((exposure.scale() - (1.0 / 100.0) / (1.2 * 256.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn white_balance_keeps_reference_white() {
+        let exposure = Exposure::new(2.0).white_balance(REFERENCE_WHITE);
+        let white = exposure.apply(Color::new(1.0, 1.0, 1.0));
+
+        for c in [white.x(), white.y(), white.z()].iter() {
+            assert!((c - 2.0).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use crate::vec3::Color;
+use rayon::prelude::*;
+use std::f64::consts::PI;
+
+/// A pixel reconstruction filter
+///
+/// Filters are evaluated at the offset of a sample from a pixel center, in
+/// pixels, and are zero beyond their radius.
+pub trait Filter: Send + Sync + std::fmt::Debug {
+    /// Returns the radius of the filter in pixels
+    fn radius(&self) -> f64;
+
+    /// Evaluate the filter at the offset (x, y) from the pixel center
+    fn eval(&self, x: f64, y: f64) -> f64;
+}
+
+/// Weighs all samples within the radius equally
+///
+/// The support is half open, so that with a radius of 0.5 every sample lands
+/// in exactly one pixel, the one it lies in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoxFilter {
+    radius: f64,
+}
+
+impl BoxFilter {
+    pub fn new(radius: f64) -> Self {
+        Self { radius }
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn eval(&self, x: f64, y: f64) -> f64 {
+        // offsets are from the pixel center to the sample, so a sample on
+        // the boundary between two pixels belongs to the later one
+        let inside = |d: f64| -self.radius < d && d <= self.radius;
+
+        if inside(x) && inside(y) {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Weighs samples linearly falling off to zero at the radius
+#[derive(Clone, Debug, PartialEq)]
+pub struct TentFilter {
+    radius: f64,
+}
+
+impl TentFilter {
+    pub fn new(radius: f64) -> Self {
+        Self { radius }
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn eval(&self, x: f64, y: f64) -> f64 {
+        (self.radius - x.abs()).max(0.0) * (self.radius - y.abs()).max(0.0)
+    }
+}
+
+/// A gaussian with standard deviation sigma, shifted to reach zero at the radius
+#[derive(Clone, Debug, PartialEq)]
+pub struct GaussianFilter {
+    radius: f64,
+    sigma: f64,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: f64, sigma: f64) -> Self {
+        Self { radius, sigma }
+    }
+
+    fn gaussian(&self, x: f64) -> f64 {
+        let g = |x: f64| (-x * x / (2.0 * self.sigma * self.sigma)).exp();
+        (g(x) - g(self.radius)).max(0.0)
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn eval(&self, x: f64, y: f64) -> f64 {
+        self.gaussian(x) * self.gaussian(y)
+    }
+}
+
+/// The Mitchell-Netravali cubic filter, with B and C trading blurring against ringing
+///
+/// B = C = 1/3 is the commonly recommended choice.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MitchellFilter {
+    radius: f64,
+    b: f64,
+    c: f64,
+}
+
+impl MitchellFilter {
+    pub fn new(radius: f64, b: f64, c: f64) -> Self {
+        Self { radius, b, c }
+    }
+
+    /// The 1D cubic, defined on [-2, 2]
+    fn mitchell(&self, x: f64) -> f64 {
+        let (b, c) = (self.b, self.c);
+        let x = x.abs();
+
+        let value = if x < 1.0 {
+            (12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b)
+        } else if x < 2.0 {
+            (-b - 6.0 * c) * x * x * x
+                + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c)
+        } else {
+            0.0
+        };
+
+        value / 6.0
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn eval(&self, x: f64, y: f64) -> f64 {
+        // scale the filter support to the radius
+        self.mitchell(2.0 * x / self.radius) * self.mitchell(2.0 * y / self.radius)
+    }
+}
+
+/// A sinc windowed by a wider sinc, with as many lobes as the radius in pixels
+#[derive(Clone, Debug, PartialEq)]
+pub struct LanczosFilter {
+    radius: f64,
+}
+
+impl LanczosFilter {
+    pub fn new(radius: f64) -> Self {
+        Self { radius }
+    }
+
+    fn lanczos(&self, x: f64) -> f64 {
+        let sinc = |x: f64| {
+            if x.abs() < 1e-5 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            }
+        };
+
+        if x.abs() > self.radius {
+            0.0
+        } else {
+            sinc(x) * sinc(x / self.radius)
+        }
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn eval(&self, x: f64, y: f64) -> f64 {
+        self.lanczos(x) * self.lanczos(y)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct FilmPixel {
+    weighted_sum: Color,
+    weight_sum: f64,
+}
+
+/// Accumulates samples into pixels weighted by a reconstruction filter
+///
+/// Samples are given in raster coordinates, x to the right and y downwards,
+/// with the center of the pixel in column i and row j at (i + 0.5, j + 0.5).
+/// Each sample is splatted to all pixels within the radius of the filter.
+#[derive(Debug)]
+pub struct Film {
+    width: usize,
+    height: usize,
+    filter: Box<dyn Filter>,
+    rows: Vec<Vec<FilmPixel>>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Box<dyn Filter>) -> Self {
+        Self {
+            width,
+            height,
+            filter,
+            rows: vec![vec![FilmPixel::default(); width]; height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Splat a batch of samples, given as raster position and color, to the film
+    ///
+    /// The affected rows are processed in parallel, but every pixel accumulates
+    /// the samples in the order of the batch. The result therefore does not
+    /// depend on thread scheduling.
+    pub fn add_samples(&mut self, samples: &[(f64, f64, Color)]) {
+        let radius = self.filter.radius();
+        let filter = &*self.filter;
+        let width = self.width;
+
+        // the range of pixel indices whose centers are within the radius
+        let range = |min: f64, max: f64, n: usize| {
+            let first = (min - 0.5 - radius).ceil().max(0.0) as usize;
+            let last = (max - 0.5 + radius).floor().min(n as f64 - 1.0);
+            (first, last)
+        };
+
+        let y_min = samples.iter().map(|s| s.1).fold(f64::INFINITY, f64::min);
+        let y_max = samples
+            .iter()
+            .map(|s| s.1)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let (first_row, last_row) = range(y_min, y_max, self.height);
+
+        if last_row < first_row as f64 {
+            return;
+        }
+
+        self.rows[first_row..=last_row as usize]
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(offset, pixels)| {
+                let center_y = (first_row + offset) as f64 + 0.5;
+
+                for &(x, y, color) in samples {
+                    if (center_y - y).abs() > radius {
+                        continue;
+                    }
+
+                    let (first_col, last_col) = range(x, x, width);
+                    if last_col < first_col as f64 {
+                        continue;
+                    }
+
+                    for (col, pixel) in pixels
+                        .iter_mut()
+                        .enumerate()
+                        .take(last_col as usize + 1)
+                        .skip(first_col)
+                    {
+                        let weight = filter.eval(col as f64 + 0.5 - x, center_y - y);
+
+                        if weight != 0.0 {
+                            pixel.weighted_sum += weight * color;
+                            pixel.weight_sum += weight;
+                        }
+                    }
+                }
+            });
+    }
+
+    /// Returns the filtered colors of all pixels, row by row from the top
+    pub fn colors(&self) -> Vec<Color> {
+        self.rows
+            .iter()
+            .flatten()
+            .map(|pixel| {
+                if pixel.weight_sum > 0.0 {
+                    pixel.weighted_sum / pixel.weight_sum
+                } else {
+                    Color::new(0.0, 0.0, 0.0)
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_filter_samples_on_pixel_boundaries() {
+        let mut film = Film::new(2, 2, Box::new(BoxFilter::new(0.5)));
+        film.add_samples(&[(1.0, 1.0, Color::new(1.0, 0.0, 0.0))]);
+
+        let covered = film
+            .colors()
+            .iter()
+            .filter(|&&color| color != Color::new(0.0, 0.0, 0.0))
+            .count();
+
+        assert_eq!(covered, 1);
+    }
+}
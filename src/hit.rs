@@ -2,11 +2,15 @@ use crate::material::Material;
 use crate::ray::Ray;
 use crate::vec3::{Point3, Vec3};
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct HitRecord<'mat> {
     point: Point3,
     normal: Vec3,
     t: f64,
+    u: f64,
+    v: f64,
+    dpdu: Vec3,
+    dpdv: Vec3,
     front_face: bool,
     mat: &'mat dyn Material,
 }
@@ -35,24 +39,109 @@ impl HitRecord<'_> {
         self.t
     }
 
+    /// Returns the surface coordinates (u, v) of the hit point
+    pub fn uv(&self) -> (f64, f64) {
+        (self.u, self.v)
+    }
+
+    /// Returns the partial derivative of the hit point with respect to u
+    pub fn dpdu(&self) -> Vec3 {
+        self.dpdu
+    }
+
+    /// Returns the partial derivative of the hit point with respect to v
+    pub fn dpdv(&self) -> Vec3 {
+        self.dpdv
+    }
+
     pub fn front_face(&self) -> bool {
         self.front_face
     }
 
+    /// Returns the normal pointing out of the surface, regardless of the side which was hit
+    pub fn outward_normal(&self) -> Vec3 {
+        if self.front_face {
+            self.normal
+        } else {
+            -self.normal
+        }
+    }
+
+    /// Returns whether the hit is opaque or masked out by the opacity of its material
+    ///
+    /// Partially opaque hits are kept with a probability equal to their opacity.
+    /// The decision is derived from a hash of the ray and the hit distance, so
+    /// that repeated queries for the same ray agree.
+    pub fn is_opaque(&self, ray: &Ray) -> bool {
+        let opacity = self.mat.opacity(self);
+
+        if opacity >= 1.0 {
+            return true;
+        }
+
+        let (ox, oy, oz) = ray.origin().xyz();
+        let (dx, dy, dz) = ray.direction().xyz();
+
+        hash_to_unit(&[ox, oy, oz, dx, dy, dz, self.t]) < opacity
+    }
+
+    /// Returns a copy of the hit record with the outward shading normal replaced
+    ///
+    /// The new normal is flipped to face against the ray just like the original one.
+    pub fn with_outward_normal(&self, outward_normal: Vec3) -> Self {
+        let normal = if self.front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+
+        Self {
+            normal,
+            ..self.clone()
+        }
+    }
+
     pub fn mat(&self) -> &dyn Material {
-        &*self.mat
+        self.mat
+    }
+}
+
+/// Offset along the ray to step past a masked out hit
+const MASKED_HIT_EPSILON: f64 = 1e-9;
+
+/// Hashes a number of floats to a pseudo random value in [0, 1)
+fn hash_to_unit(values: &[f64]) -> f64 {
+    let mut h: u64 = 0x9e37_79b9_7f4a_7c15;
+
+    for value in values {
+        // splitmix64 finalizer
+        h ^= value.to_bits();
+        h = h.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^= h >> 31;
     }
+
+    (h >> 11) as f64 / (1u64 << 53) as f64
 }
 
 impl<H: Hittable> Hittable for &[H] {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut latest_hit = None;
         let mut closest_so_far = t_max;
 
         for hittable in *self {
-            if let Some(hr) = hittable.hit(ray, t_min, closest_so_far) {
-                closest_so_far = hr.t();
-                latest_hit = Some(hr);
+            let mut t_min = t_min;
+
+            // continue past hits which are masked out by the material's opacity
+            while let Some(hr) = hittable.hit(ray, t_min, closest_so_far) {
+                if hr.is_opaque(ray) {
+                    closest_so_far = hr.t();
+                    latest_hit = Some(hr);
+                    break;
+                }
+
+                t_min = hr.t() + MASKED_HIT_EPSILON;
             }
         }
 
@@ -61,7 +150,7 @@ impl<H: Hittable> Hittable for &[H] {
 }
 
 pub trait Hittable {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
 }
 
 #[derive(Debug)]
@@ -79,6 +168,19 @@ impl Sphere {
             mat: material,
         }
     }
+
+    /// Maps a point on the unit sphere to its surface coordinates (u, v)
+    ///
+    /// u runs around the y axis starting at x = -1, v runs from y = -1 to y = 1.
+    fn uv(p: Point3) -> (f64, f64) {
+        let theta = (-p.y()).acos();
+        let phi = (-p.z()).atan2(p.x()) + std::f64::consts::PI;
+
+        (
+            phi / (2.0 * std::f64::consts::PI),
+            theta / std::f64::consts::PI,
+        )
+    }
 }
 
 impl Hittable for Sphere {
@@ -107,12 +209,33 @@ impl Hittable for Sphere {
         let t = root;
         let point = ray.at(t);
 
-        let (front_face, normal) = HitRecord::face_normal(ray, (point - self.center) / self.radius);
+        let outward_normal = (point - self.center) / self.radius;
+        let (front_face, normal) = HitRecord::face_normal(ray, outward_normal);
+        let (u, v) = Self::uv(outward_normal);
+
+        // tangents along u and v, following the parametrization used in Self::uv()
+        let phi = 2.0 * std::f64::consts::PI * u;
+        let theta = std::f64::consts::PI * v;
+        let dpdu = 2.0
+            * std::f64::consts::PI
+            * self.radius
+            * Vec3::new(outward_normal.z(), 0.0, -outward_normal.x());
+        let dpdv = std::f64::consts::PI
+            * self.radius
+            * Vec3::new(
+                -phi.cos() * theta.cos(),
+                theta.sin(),
+                phi.sin() * theta.cos(),
+            );
 
         Some(HitRecord {
             point,
             normal,
             t,
+            u,
+            v,
+            dpdu,
+            dpdv,
             front_face,
             mat: &*self.mat,
         })
@@ -0,0 +1,582 @@
+use crate::cam::{basis, Camera};
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::{Color, Point3, Vec3};
+use std::f64::consts::PI;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// One spherical surface of a lens system, or its aperture stop
+///
+/// All lengths are in millimeters. The radius is positive if the center of
+/// curvature lies towards the film, and 0.0 for the flat aperture stop.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LensSurface {
+    radius: f64,
+    thickness: f64,
+    ior: f64,
+    aperture: f64,
+}
+
+impl LensSurface {
+    /// Create a new LensSurface
+    ///
+    /// thickness is the distance along the optical axis to the next surface,
+    /// ior the index of refraction of the medium up to the next surface, with
+    /// 0.0 meaning air, and aperture the diameter of the surface.
+    pub fn new(radius: f64, thickness: f64, ior: f64, aperture: f64) -> Self {
+        Self {
+            radius,
+            thickness,
+            ior,
+            aperture,
+        }
+    }
+}
+
+/// Read a lens prescription, listing the surfaces from the object to the film side
+///
+/// Every line holds the radius, thickness, index of refraction and aperture of
+/// one surface, separated by whitespace. Everything following a '#' is ignored.
+pub fn read_prescription<P: AsRef<Path>>(path: P) -> io::Result<Vec<LensSurface>> {
+    parse_prescription(&fs::read_to_string(path)?)
+}
+
+fn parse_prescription(text: &str) -> io::Result<Vec<LensSurface>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    let mut surfaces = Vec::new();
+
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("");
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let values = line
+            .split_whitespace()
+            .map(|value| {
+                value
+                    .parse::<f64>()
+                    .map_err(|_| invalid("invalid number in lens prescription"))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        if values.len() != 4 {
+            return Err(invalid("expected 4 values per lens surface"));
+        }
+
+        surfaces.push(LensSurface::new(values[0], values[1], values[2], values[3]));
+    }
+
+    if surfaces.is_empty() {
+        return Err(invalid("empty lens prescription"));
+    }
+
+    Ok(surfaces)
+}
+
+/// A lens surface placed on the optical axis
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Element {
+    radius: f64,
+    z: f64,
+    ior: f64,
+    aperture_radius: f64,
+}
+
+/// A sequence of lens elements in camera space
+///
+/// The film lies in the plane z = 0 and the lenses along the positive z axis,
+/// which points towards the scene. Lengths are in millimeters.
+#[derive(Clone, Debug, PartialEq)]
+struct LensSystem {
+    elements: Vec<Element>,
+}
+
+impl LensSystem {
+    /// Place the surfaces so that the last one is rear_distance in front of the film
+    fn new(surfaces: &[LensSurface], rear_distance: f64) -> Self {
+        let mut z = rear_distance;
+        let mut elements = vec![];
+
+        for (i, surface) in surfaces.iter().enumerate().rev() {
+            if i + 1 < surfaces.len() {
+                z += surface.thickness;
+            }
+
+            elements.push(Element {
+                radius: surface.radius,
+                z,
+                ior: surface.ior,
+                aperture_radius: surface.aperture / 2.0,
+            });
+        }
+
+        elements.reverse();
+        Self { elements }
+    }
+
+    fn rear(&self) -> &Element {
+        self.elements.last().expect("lens system without surfaces")
+    }
+
+    fn front(&self) -> &Element {
+        self.elements.first().expect("lens system without surfaces")
+    }
+
+    /// The index of refraction between the surfaces i and i + 1, with -1 being the scene
+    fn ior(&self, i: isize) -> f64 {
+        match i {
+            i if i < 0 => 1.0,
+            i => match self.elements[i as usize].ior {
+                0.0 => 1.0,
+                ior => ior,
+            },
+        }
+    }
+
+    /// Intersect a ray with an element and refract it from eta_i into eta_t
+    ///
+    /// Returns None if the ray misses the element, is blocked by its aperture or
+    /// is totally internally reflected.
+    fn refract(
+        element: &Element,
+        origin: Point3,
+        direction: Vec3,
+        eta_i: f64,
+        eta_t: f64,
+    ) -> Option<(Point3, Vec3)> {
+        let center = Point3::new(0.0, 0.0, element.z - element.radius);
+
+        let t = if element.radius == 0.0 {
+            (element.z - origin.z()) / direction.z()
+        } else {
+            let oc = origin - center;
+            let a = direction.length_squared();
+            let half_b = oc.dot(direction);
+            let c = oc.length_squared() - element.radius * element.radius;
+            let discriminant = half_b * half_b - a * c;
+
+            if discriminant < 0.0 {
+                return None;
+            }
+
+            // the surface is the cap of the sphere around its vertex
+            let sqrtd = discriminant.sqrt();
+            [(-half_b - sqrtd) / a, (-half_b + sqrtd) / a]
+                .iter()
+                .copied()
+                .filter(|&t| t > 0.0)
+                .min_by(|&t1, &t2| {
+                    let dz = |t: f64| (origin.z() + t * direction.z() - element.z).abs();
+                    dz(t1).total_cmp(&dz(t2))
+                })?
+        };
+
+        if t.is_nan() || t <= 0.0 {
+            return None;
+        }
+
+        let point = origin + t * direction;
+        if point.x() * point.x() + point.y() * point.y()
+            > element.aperture_radius * element.aperture_radius
+        {
+            return None;
+        }
+
+        if element.radius == 0.0 {
+            return Some((point, direction));
+        }
+
+        let direction = direction.unit();
+        let normal = (point - center).unit();
+        let normal = if normal.dot(direction) > 0.0 {
+            -normal
+        } else {
+            normal
+        };
+
+        let ratio = eta_i / eta_t;
+        let cos_theta = (-direction).dot(normal).min(1.0);
+        if ratio * ratio * (1.0 - cos_theta * cos_theta) > 1.0 {
+            return None;
+        }
+
+        Some((point, direction.refract(normal, ratio)))
+    }
+
+    /// Trace a ray from the film side through all elements to the scene
+    fn trace_from_film(&self, origin: Point3, direction: Vec3) -> Option<(Point3, Vec3)> {
+        (0..self.elements.len())
+            .rev()
+            .try_fold((origin, direction), |(origin, direction), i| {
+                let i = i as isize;
+                Self::refract(
+                    &self.elements[i as usize],
+                    origin,
+                    direction,
+                    self.ior(i),
+                    self.ior(i - 1),
+                )
+            })
+    }
+
+    /// Trace a ray from the scene through all elements to the film side
+    fn trace_from_scene(&self, origin: Point3, direction: Vec3) -> Option<(Point3, Vec3)> {
+        (0..self.elements.len()).try_fold((origin, direction), |(origin, direction), i| {
+            let i = i as isize;
+            Self::refract(
+                &self.elements[i as usize],
+                origin,
+                direction,
+                self.ior(i - 1),
+                self.ior(i),
+            )
+        })
+    }
+
+    /// Returns the z of the focal point and principal plane on the side a
+    /// paraxial ray at height h leaves to
+    fn cardinal_points(h: f64, (origin, direction): (Point3, Vec3)) -> (f64, f64) {
+        let focal_z = origin.z() - origin.x() / direction.x() * direction.z();
+        let principal_z = origin.z() + (h - origin.x()) / direction.x() * direction.z();
+
+        (focal_z, principal_z)
+    }
+
+    /// Returns the rear distance focusing on a plane focus_dist in front of the film
+    ///
+    /// Uses the thick lens approximation of the system, returns None if the
+    /// system can not focus that close.
+    fn focus(surfaces: &[LensSurface], h: f64, focus_dist: f64) -> Option<f64> {
+        let rear_distance = surfaces.last()?.thickness;
+        let system = Self::new(surfaces, rear_distance);
+
+        let scene_ray = system.trace_from_scene(
+            Point3::new(h, 0.0, system.front().z + 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        )?;
+        let film_ray =
+            system.trace_from_film(Point3::new(h, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0))?;
+
+        let (focal_z_film, principal_z_film) = Self::cardinal_points(h, scene_ray);
+        let (_, principal_z_scene) = Self::cardinal_points(h, film_ray);
+        let focal_length = principal_z_film - focal_z_film;
+
+        // moving the lens by delta, the object distance from the scene side
+        // principal plane is l - a and the image distance a, with
+        // 1 / (l - a) + 1 / a = 1 / focal_length
+        let l = focus_dist - principal_z_scene + principal_z_film;
+        let discriminant = l * l - 4.0 * focal_length * l;
+
+        if discriminant.is_nan() || discriminant < 0.0 || focal_length <= 0.0 {
+            return None;
+        }
+
+        let a = (l - discriminant.sqrt()) / 2.0;
+        Some(rear_distance + a - principal_z_film)
+    }
+
+    /// Returns the radius around the axis on the plane of the rear element
+    /// that bounds all rays from the film within film_radius passing the system
+    fn pupil_radius(&self, film_radius: f64) -> f64 {
+        let rear = self.rear();
+        let (film_steps, pupil_steps) = (16, 64);
+        let cell = 2.0 * rear.aperture_radius / pupil_steps as f64;
+
+        let mut radius: f64 = 0.0;
+
+        for i in 0..=film_steps {
+            let film_point = Point3::new(film_radius * i as f64 / film_steps as f64, 0.0, 0.0);
+
+            for j in 0..pupil_steps {
+                for k in 0..pupil_steps {
+                    let x = -rear.aperture_radius + (j as f64 + 0.5) * cell;
+                    let y = -rear.aperture_radius + (k as f64 + 0.5) * cell;
+                    let pupil_point = Point3::new(x, y, rear.z);
+
+                    if self
+                        .trace_from_film(film_point, pupil_point - film_point)
+                        .is_some()
+                    {
+                        radius = radius.max((x * x + y * y).sqrt());
+                    }
+                }
+            }
+        }
+
+        if radius == 0.0 {
+            rear.aperture_radius
+        } else {
+            (radius + cell).min(rear.aperture_radius)
+        }
+    }
+}
+
+/// A camera tracing rays through a system of spherical lens elements
+///
+/// Vignetting, distortion and the shape of the bokeh follow from the lens
+/// system. Rays from the film are sampled over the exit pupil, rays blocked
+/// by the lens system see nothing. The rays are weighted by the irradiance they
+/// carry onto the film, so the image gets brighter with larger apertures.
+/// Points are not projected, as the lens system has no closed form projection.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RealisticCamera {
+    system: LensSystem,
+    film_width: f64,
+    film_height: f64,
+    pupil_radius: f64,
+    units_per_mm: f64,
+    origin: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Camera for RealisticCamera {
+    fn get_ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<Ray> {
+        self.sample_ray(u, v, sampler).map(|(ray, _)| ray)
+    }
+
+    fn sample_ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<(Ray, Color)> {
+        // the lens system flips the image
+        let film_point = Point3::new(
+            (0.5 - u) * self.film_width,
+            (0.5 - v) * self.film_height,
+            0.0,
+        );
+        let pupil_point = self.pupil_radius * Vec3::random_in_unit_disk(sampler)
+            + Point3::new(0.0, 0.0, self.system.rear().z);
+
+        let film_direction = pupil_point - film_point;
+
+        let (origin, direction) = self.system.trace_from_film(film_point, film_direction)?;
+
+        let to_world = |p: Vec3| p.x() * self.u + p.y() * self.v - p.z() * self.w;
+
+        // the irradiance on the film is the radiance integrated over the exit
+        // pupil, with the cosine falloff towards the edges of the film
+        let cos_theta = film_direction.unit().z();
+        let rear_z = self.system.rear().z;
+        let pupil_area = PI * self.pupil_radius * self.pupil_radius;
+        let weight = cos_theta.powi(4) * pupil_area / (rear_z * rear_z);
+
+        Some((
+            Ray::new(
+                self.origin + self.units_per_mm * to_world(origin),
+                to_world(direction),
+            ),
+            Color::new(weight, weight, weight),
+        ))
+    }
+}
+
+/// Builds a RealisticCamera
+///
+/// look_from is the center of the film.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RealisticCameraBuilder {
+    surfaces: Vec<LensSurface>,
+    aperture_stop: Option<f64>,
+    film_diagonal: f64,
+    aspect_ratio: f64,
+    focus_dist: f64,
+    units_per_mm: f64,
+    look_from: Point3,
+    look_at: Point3,
+    vup: Vec3,
+}
+
+impl RealisticCameraBuilder {
+    /// Create a builder for a lens system with the surfaces from the object to
+    /// the film side
+    ///
+    /// Panics if there are no surfaces.
+    pub fn new(surfaces: Vec<LensSurface>) -> Self {
+        assert!(!surfaces.is_empty(), "lens system without surfaces");
+
+        Self {
+            surfaces,
+            aperture_stop: None,
+            film_diagonal: 43.27,
+            aspect_ratio: 16.0 / 9.0,
+            focus_dist: 1.0,
+            units_per_mm: 0.001,
+            look_from: Point3::new(0.0, 0.0, 0.0),
+            look_at: Point3::new(0.0, 0.0, 1.0),
+            vup: Point3::new(0.0, 1.0, 0.0),
+        }
+    }
+
+    /// Sets the diameter of the aperture stop in mm, instead of the prescribed one
+    pub fn aperture_stop(&mut self, aperture_stop: f64) -> &mut Self {
+        self.aperture_stop = Some(aperture_stop);
+        self
+    }
+
+    /// Sets the diagonal of the film in mm, 43.27 for full frame
+    pub fn film_diagonal(&mut self, film_diagonal: f64) -> &mut Self {
+        self.film_diagonal = film_diagonal;
+        self
+    }
+
+    pub fn aspect_ratio(&mut self, aspect_ratio: f64) -> &mut Self {
+        self.aspect_ratio = aspect_ratio;
+        self
+    }
+
+    /// Sets the distance from the film to the plane in focus in world units
+    pub fn focus_dist(&mut self, focus_dist: f64) -> &mut Self {
+        self.focus_dist = focus_dist;
+        self
+    }
+
+    /// Sets the size of a millimeter of the lens system in world units
+    pub fn units_per_mm(&mut self, units_per_mm: f64) -> &mut Self {
+        self.units_per_mm = units_per_mm;
+        self
+    }
+
+    pub fn look_from(&mut self, look_from: Point3) -> &mut Self {
+        self.look_from = look_from;
+        self
+    }
+
+    pub fn look_at(&mut self, look_at: Point3) -> &mut Self {
+        self.look_at = look_at;
+        self
+    }
+
+    pub fn view_up(&mut self, view_up: Vec3) -> &mut Self {
+        self.vup = view_up;
+        self
+    }
+
+    /// Build the camera, focusing the lens system by moving it along the axis
+    ///
+    /// If the system can not focus at focus_dist, the prescribed distance of
+    /// the last surface to the film is kept.
+    pub fn build(&self) -> RealisticCamera {
+        let surfaces: Vec<_> = self
+            .surfaces
+            .iter()
+            .map(|surface| match self.aperture_stop {
+                Some(aperture) if surface.radius == 0.0 => LensSurface {
+                    aperture,
+                    ..*surface
+                },
+                _ => *surface,
+            })
+            .collect();
+
+        let rear_distance = LensSystem::focus(
+            &surfaces,
+            0.001 * self.film_diagonal,
+            self.focus_dist / self.units_per_mm,
+        )
+        .unwrap_or(surfaces[surfaces.len() - 1].thickness);
+        let system = LensSystem::new(&surfaces, rear_distance);
+
+        let film_height = self.film_diagonal / (1.0 + self.aspect_ratio * self.aspect_ratio).sqrt();
+        let film_width = self.aspect_ratio * film_height;
+        let pupil_radius = system.pupil_radius(self.film_diagonal / 2.0);
+
+        let (u, v, w) = basis(self.look_from, self.look_at, self.vup);
+
+        RealisticCamera {
+            system,
+            film_width,
+            film_height,
+            pupil_radius,
+            units_per_mm: self.units_per_mm,
+            origin: self.look_from,
+            u,
+            v,
+            w,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::Independent;
+
+    fn double_gauss() -> Vec<LensSurface> {
+        parse_prescription(include_str!("../lenses/dgauss50mm.txt")).expect("valid prescription")
+    }
+
+    #[test]
+    fn parse_lens_prescription() {
+        let surfaces =
+            parse_prescription("# comment\n\n 29.5 3.76 1.67 25.2 # front\n0 4.5 0 17\n")
+                .expect("valid prescription");
+
+        assert_eq!(
+            surfaces,
+            vec![
+                LensSurface::new(29.5, 3.76, 1.67, 25.2),
+                LensSurface::new(0.0, 4.5, 0.0, 17.0),
+            ]
+        );
+        assert_eq!(double_gauss().len(), 11);
+    }
+
+    #[test]
+    fn reject_surface_without_aperture() {
+        let error = parse_prescription("29.5 3.76 1.67\n").unwrap_err();
+
+        assert_eq!(error.to_string(), "expected 4 values per lens surface");
+    }
+
+    #[test]
+    fn reject_glass_names() {
+        let error = parse_prescription("29.5 3.76 BK7 25.2\n").unwrap_err();
+
+        assert_eq!(error.to_string(), "invalid number in lens prescription");
+    }
+
+    #[test]
+    fn reject_prescription_of_comments() {
+        let error = parse_prescription("# radius thickness ior aperture\n\n").unwrap_err();
+
+        assert_eq!(error.to_string(), "empty lens prescription");
+    }
+
+    #[test]
+    #[should_panic(expected = "lens system without surfaces")]
+    fn builder_without_surfaces() {
+        RealisticCameraBuilder::new(vec![]);
+    }
+
+    #[test]
+    fn rays_are_weighted_by_film_irradiance() {
+        let camera = RealisticCameraBuilder::new(double_gauss())
+            .focus_dist(5.0)
+            .build();
+        let mut sampler = Independent::new(0);
+
+        let rear_z = camera.system.rear().z;
+        let max_weight = PI * camera.pupil_radius * camera.pupil_radius / (rear_z * rear_z);
+
+        let mean_weight = |u: f64, v: f64, sampler: &mut Independent| {
+            (0..256)
+                .map(|_| {
+                    camera
+                        .sample_ray(u, v, sampler)
+                        .map_or(0.0, |(_, weight)| weight.x())
+                })
+                .sum::<f64>()
+                / 256.0
+        };
+
+        let center = mean_weight(0.5, 0.5, &mut sampler);
+        let corner = mean_weight(0.0, 0.0, &mut sampler);
+
+        assert!(center > 0.0 && center <= max_weight);
+        assert!(corner < center);
+    }
+}
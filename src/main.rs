@@ -1,19 +1,35 @@
 #![forbid(unsafe_code)]
 #![allow(dead_code)]
 
+mod adaptive;
+mod animation;
 mod cam;
+mod exposure;
+mod film;
 mod hit;
+mod lens;
 mod material;
+mod medium;
+mod phase;
 mod ray;
+mod sampler;
+mod texture;
 mod vec3;
 
-use cam::CameraBuilder;
+use adaptive::{AdaptiveSampling, PixelStats};
+use animation::CameraAnimation;
+use cam::{Camera, CameraBuilder};
+use film::{BoxFilter, Film};
 use hit::{Hittable, Sphere};
-use material::{Dielectric, Lambertian, Material, Metal};
+use material::{Dielectric, Lambertian, Lobe, Material, Metal};
+use medium::{Interaction, Medium};
 use rand::prelude::*;
 use ray::Ray;
 use rayon::prelude::*;
-use std::io;
+use sampler::{Sampler, Sobol};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
 use std::sync::{self, atomic};
 use std::thread;
 use vec3::{Color, Point3, Vec3};
@@ -50,21 +66,174 @@ pub fn to_ppm<W: io::Write>(
     Ok(())
 }
 
-fn ray_color<H: Hittable>(ray: &Ray, world: H, depth: u32) -> Color {
-    if depth == 0 {
+/// Optional, biased techniques suppressing fireflies, all off by default
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Regularization {
+    /// The maximum channel value a camera sample receives via two or more bounces
+    max_radiance: Option<f64>,
+    /// How much specular lobes are roughened once a path bounced diffusely
+    roughening: Option<f64>,
+}
+
+impl Regularization {
+    pub fn max_radiance(mut self, max_radiance: f64) -> Self {
+        self.max_radiance = Some(max_radiance);
+        self
+    }
+
+    pub fn roughening(mut self, roughening: f64) -> Self {
+        self.roughening = Some(roughening);
+        self
+    }
+
+    /// Scale down the radiance arriving at a path vertex so that its
+    /// contribution to the camera sample, throughput times radiance, has no
+    /// channel exceeding max_radiance
+    fn clamp(&self, radiance: Color, throughput: Color) -> Color {
+        let (r, g, b) = (throughput * radiance).xyz();
+        let max = r.max(g).max(b);
+
+        match self.max_radiance {
+            Some(max_radiance) if max > max_radiance => radiance * (max_radiance / max),
+            _ => radiance,
+        }
+    }
+
+    /// Clamp the radiance arriving after the second bounce of a path, so that
+    /// all light reaching the camera sample via two or more bounces is clamped
+    /// once as a whole
+    fn indirect(&self, radiance: Color, path: PathState) -> Color {
+        if path.bounces == 2 {
+            self.clamp(radiance, path.throughput)
+        } else {
+            radiance
+        }
+    }
+}
+
+/// The state of a path being traced
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct PathState {
+    /// The number of bounces left
+    depth: u32,
+    /// Whether the path bounced diffusely before
+    after_diffuse: bool,
+    /// The number of bounces so far
+    bounces: u32,
+    /// The weight of radiance arriving at the current vertex in the camera sample
+    throughput: Color,
+}
+
+impl PathState {
+    /// Create the state of a camera ray with the given weight
+    fn new(depth: u32, weight: Color) -> Self {
+        Self {
+            depth,
+            after_diffuse: false,
+            bounces: 0,
+            throughput: weight,
+        }
+    }
+
+    /// The state after scattering by the given lobe, attenuating by attenuation
+    fn next(self, lobe: Lobe, attenuation: Color) -> Self {
+        Self {
+            depth: self.depth - 1,
+            after_diffuse: self.after_diffuse || lobe == Lobe::Diffuse,
+            bounces: self.bounces + 1,
+            throughput: self.throughput * attenuation,
+        }
+    }
+}
+
+/// Returns the color seen along a ray
+///
+/// medium is the medium the ray currently travels through, atmosphere the one
+/// filling the scene outside of closed surfaces.
+fn ray_color<H: Hittable + Copy>(
+    ray: &Ray,
+    world: H,
+    atmosphere: Option<&dyn Medium>,
+    medium: Option<&dyn Medium>,
+    regularization: &Regularization,
+    sampler: &mut dyn Sampler,
+    path: PathState,
+) -> Color {
+    if path.depth == 0 {
         return Color::new(0.0, 0.0, 0.0);
     }
 
-    if let Some(hr) = world.hit(&ray, 0.001, f64::INFINITY) {
-        if let Some(scatter) = hr.mat().scatter(ray, &hr) {
-            scatter.attenuation() * ray_color(scatter.scattered(), world, depth - 1)
+    let hit = world.hit(ray, 0.001, f64::INFINITY);
+
+    // inside a medium the ray may scatter before reaching the next surface
+    let (transmittance, emitted) = match medium {
+        Some(medium) => match medium.sample(
+            ray,
+            hit.as_ref().map_or(f64::INFINITY, |hr| hr.t()),
+            sampler,
+        ) {
+            Interaction::Scatter { scatter, emitted } => {
+                let next = path.next(scatter.lobe(), scatter.attenuation());
+                let incoming = ray_color(
+                    scatter.scattered(),
+                    world,
+                    atmosphere,
+                    Some(medium),
+                    regularization,
+                    sampler,
+                    next,
+                );
+                return emitted + scatter.attenuation() * regularization.indirect(incoming, next);
+            }
+            Interaction::Transmit {
+                transmittance,
+                emitted,
+            } => (transmittance, emitted),
+        },
+        None => (Color::new(1.0, 1.0, 1.0), Color::new(0.0, 0.0, 0.0)),
+    };
+
+    if let Some(hr) = hit {
+        if let Some(mut scatter) = hr.mat().scatter(ray, &hr, sampler) {
+            if let (Some(roughening), true, Lobe::Specular) = (
+                regularization.roughening,
+                path.after_diffuse,
+                scatter.lobe(),
+            ) {
+                scatter = scatter.roughened(roughening, hr.normal(), sampler);
+            }
+
+            // rays passing through the surface enter or leave its interior medium
+            let medium = if scatter.scattered().direction().dot(hr.normal()) >= 0.0 {
+                medium
+            } else if hr.front_face() {
+                hr.mat().interior()
+            } else {
+                atmosphere
+            };
+
+            let attenuation = transmittance * scatter.attenuation();
+            let next = path.next(scatter.lobe(), attenuation);
+            let incoming = ray_color(
+                scatter.scattered(),
+                world,
+                atmosphere,
+                medium,
+                regularization,
+                sampler,
+                next,
+            );
+
+            emitted + attenuation * regularization.indirect(incoming, next)
         } else {
-            Color::new(0.0, 0.0, 0.0)
+            emitted
         }
     } else {
         let unit_direction = ray.direction().unit();
         let t = 0.5 * (unit_direction.y() + 1.0);
-        (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
+        emitted
+            + transmittance
+                * ((1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0))
     }
 }
 
@@ -82,16 +251,152 @@ fn hit_sphere(center: Point3, radius: f64, ray: &Ray) -> f64 {
     }
 }
 
+/// Settings shared by all images rendered
+struct RenderSettings<'a> {
+    image_width: usize,
+    image_height: usize,
+    max_samples: u32,
+    max_depth: u32,
+    adaptive: Option<AdaptiveSampling>,
+    atmosphere: Option<&'a dyn Medium>,
+    regularization: Regularization,
+}
+
+/// Render an image of the world to the film
+///
+/// Returns the statistics of the samples of every pixel, row by row from the top.
+fn render<H, S>(
+    settings: &RenderSettings,
+    camera: &dyn Camera,
+    world: H,
+    sampler: &S,
+    film: &mut Film,
+) -> Vec<PixelStats>
+where
+    H: Hittable + Copy + Send + Sync,
+    S: Sampler + Clone + Send + Sync,
+{
+    let RenderSettings {
+        image_width,
+        image_height,
+        max_samples,
+        max_depth,
+        ref adaptive,
+        atmosphere,
+        ref regularization,
+    } = *settings;
+
+    // counter
+    let counter = sync::Arc::new(atomic::AtomicI32::new(image_height as i32));
+
+    // status thread, prints number of remaining lines to be calculated
+    let join_handle = thread::spawn({
+        let counter = counter.clone();
+        move || loop {
+            let count = counter.load(atomic::Ordering::Relaxed);
+            eprintln!("{:4} lines remaining", count);
+
+            if count <= 0 {
+                break;
+            }
+
+            thread::sleep(std::time::Duration::from_millis(1_000));
+        }
+    });
+
+    // rows are rendered in parallel in bands, whose samples are then splatted
+    // to the film in a fixed order, so the image is reproducible
+    let band_height = 16;
+    let rows: Vec<usize> = (0..image_height).rev().collect();
+
+    let counter = &counter;
+    let mut pixel_stats = Vec::with_capacity(image_width * image_height);
+
+    for band in rows.chunks(band_height) {
+        let pixels: Vec<_> = band
+            .par_iter()
+            .map(move |&j| {
+                counter.fetch_sub(1, atomic::Ordering::Relaxed);
+                (0..image_width).into_par_iter().map(move |i| {
+                    let mut stats = PixelStats::default();
+                    let mut samples = Vec::with_capacity(max_samples as usize);
+                    let mut sampler = sampler.clone();
+
+                    for sample in 0..max_samples {
+                        sampler.start_pixel_sample((i, j), sample);
+
+                        let (du, dv) = sampler.get_2d();
+                        let u = (i as f64 + du) / (image_width - 1) as f64;
+                        let v = (j as f64 + dv) / (image_height - 1) as f64;
+
+                        let color = match camera.sample_ray(u, v, &mut sampler) {
+                            Some((ray, weight)) => {
+                                weight
+                                    * ray_color(
+                                        &ray,
+                                        world,
+                                        atmosphere,
+                                        atmosphere,
+                                        regularization,
+                                        &mut sampler,
+                                        PathState::new(max_depth, weight),
+                                    )
+                            }
+                            None => Color::new(0.0, 0.0, 0.0),
+                        };
+
+                        // raster coordinates run from the top left corner
+                        stats.add(color);
+                        samples.push((i as f64 + du, (image_height - j) as f64 - dv, color));
+
+                        if let Some(adaptive) = adaptive {
+                            if adaptive.converged(&stats) {
+                                break;
+                            }
+                        }
+                    }
+
+                    (stats, samples)
+                })
+            })
+            .flatten()
+            .collect();
+
+        let samples: Vec<_> = pixels
+            .iter()
+            .flat_map(|(_, samples)| samples.iter().cloned())
+            .collect();
+        film.add_samples(&samples);
+
+        pixel_stats.extend(pixels.into_iter().map(|(stats, _)| stats));
+    }
+
+    join_handle.join().expect("Failed to join status thread");
+
+    pixel_stats
+}
+
 fn main() -> std::io::Result<()> {
-    let mut rng = thread_rng();
+    // global seed, the same seed renders the same image regardless of thread scheduling
+    let seed = 0;
+    let mut rng = StdRng::seed_from_u64(seed);
 
     // image
     let aspect_ratio = 16.0 / 9.0;
     let image_width = 1920;
     let image_height = (image_width as f64 / aspect_ratio) as usize;
-    let samples_per_pixel = 10;
+    let samples_per_pixel = 16;
     let max_depth = 50;
 
+    // optional adaptive sampling, replacing the fixed samples_per_pixel, e.g.
+    // Some(AdaptiveSampling::new(8, 256, 0.02)), and where to write a heatmap
+    // of the samples taken per pixel to
+    let adaptive: Option<AdaptiveSampling> = None;
+    let heatmap_path: Option<&str> = None;
+    let max_samples = adaptive
+        .as_ref()
+        .map_or(samples_per_pixel, |adaptive| adaptive.max_samples());
+
     // generate a random world
 
     let material_ground = Box::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
@@ -116,12 +421,12 @@ fn main() -> std::io::Result<()> {
                 let material: Box<dyn Material> = match choose_mat {
                     _ if choose_mat < 0.8 => {
                         // diffuse
-                        let albedo = Color::random() * Color::random();
+                        let albedo = Color::random(&mut rng) * Color::random(&mut rng);
                         Box::new(Lambertian::new(albedo))
                     }
                     _ if choose_mat < 0.95 => {
                         // metal
-                        let albedo = Color::random_range(0.5..1.0);
+                        let albedo = Color::random_range(&mut rng, 0.5..1.0);
                         let fuzz = rng.gen_range(0.0..0.5);
                         Box::new(Metal::new(albedo, fuzz))
                     }
@@ -144,73 +449,166 @@ fn main() -> std::io::Result<()> {
     world.push(Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, material2));
     world.push(Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, material3));
 
-    // camera
-    let camera = &CameraBuilder::default()
+    // optional haze filling the scene, e.g.
+    // Some(&Atmosphere::new(Color::new(0.0, 0.0, 0.0), Color::new(0.02, 0.02, 0.02),
+    //     Box::new(HenyeyGreenstein::new(0.7)), 100.0))
+    let atmosphere: Option<&dyn Medium> = None;
+
+    // optional firefly suppression, biasing the image, e.g.
+    // Regularization::default().max_radiance(10.0).roughening(0.3)
+    let regularization = Regularization::default();
+
+    let mut camera_builder = CameraBuilder::default();
+    camera_builder
         .look_from(Point3::new(13.0, 2.0, 3.0))
         .look_at(Point3::new(0.0, 0.0, 0.0))
         .view_up(Vec3::new(0.0, 1.0, 0.0))
         .vertical_fov(20.0)
         .aspect_ratio(aspect_ratio)
         .aperture(0.1)
-        .focus_dist(10.0)
-        .build();
+        .focus_dist(10.0);
+
+    // optional camera animation of the camera_builder, rendering a numbered
+    // sequence of ppm files starting with frame_prefix instead of a single
+    // image to stdout, e.g.
+    // Some(CameraAnimation::new(vec![
+    //     Keyframe::new(0.0, Point3::new(13.0, 2.0, 3.0), Point3::new(0.0, 0.0, 0.0), 20.0, 10.0),
+    //     Keyframe::new(4.0, Point3::new(3.0, 2.0, 13.0), Point3::new(0.0, 0.0, 0.0), 20.0, 10.0),
+    // ], Interpolation::CatmullRom).slerp(true))
+    // Frames already on disk are skipped, so an interrupted render resumes.
+    let animation: Option<CameraAnimation> = None;
+    let frames_per_second = 24.0;
+    let frame_prefix = "frame_";
+
+    // sampler generating the random numbers of each pixel, one of
+    // Independent::new(seed), Stratified::new(max_samples, seed),
+    // Halton::new(seed) or Sobol::new(max_samples, seed)
+    let new_sampler = |seed| Sobol::new(max_samples, seed);
+
+    if !max_samples.is_power_of_two() {
+        eprintln!(
+            "Warning: {} samples per pixel are only roughly stratified by Sobol, \
+             use a power of two",
+            max_samples
+        );
+    }
+
+    // film reconstructing the pixels from their samples, with one of BoxFilter,
+    // TentFilter, GaussianFilter, MitchellFilter or LanczosFilter, e.g.
+    // MitchellFilter::new(2.0, 1.0 / 3.0, 1.0 / 3.0)
+    let new_film = || Film::new(image_width, image_height, Box::new(BoxFilter::new(0.5)));
+
+    let settings = RenderSettings {
+        image_width,
+        image_height,
+        max_samples,
+        max_depth,
+        adaptive,
+        atmosphere,
+        regularization,
+    };
 
-    eprintln!("{:#?}", camera);
     eprintln!("Using {} raytracing threads", rayon::current_num_threads());
 
-    // counter
-    let counter = sync::Arc::new(atomic::AtomicI32::new(image_height as i32));
+    if let Some(animation) = &animation {
+        let frames = ((animation.end() - animation.start()) * frames_per_second).floor() as u64 + 1;
 
-    // status thread, prints number of remaining lines to be calculated
-    let join_handle = thread::spawn({
-        let counter = counter.clone();
-        move || loop {
-            let count = counter.load(atomic::Ordering::Relaxed);
-            eprintln!("{:4} lines remaining", count);
+        for frame in 0..frames {
+            let path = format!("{}{:04}.ppm", frame_prefix, frame);
 
-            if count <= 0 {
-                break;
+            if Path::new(&path).exists() {
+                eprintln!("Skipping frame {}, {} already exists", frame, path);
+                continue;
             }
 
-            thread::sleep(std::time::Duration::from_millis(1_000));
+            eprintln!("Rendering frame {} of {} to {}..", frame + 1, frames, path);
+
+            let time = animation.start() + frame as f64 / frames_per_second;
+            let camera = animation.apply(time, &camera_builder).build();
+
+            // every frame gets its own seed, so the noise does not stand still
+            let mut film = new_film();
+            render(
+                &settings,
+                &camera,
+                &world[..],
+                &new_sampler(seed.wrapping_add(frame)),
+                &mut film,
+            );
+
+            // write to a temporary file first, so an interrupted render never
+            // leaves an incomplete frame behind that would be skipped
+            let partial = format!("{}.partial", path);
+            let mut file = io::BufWriter::new(fs::File::create(&partial)?);
+            let exposure = camera.exposure();
+            to_ppm(
+                &mut file,
+                film.colors().into_iter().map(|color| exposure.apply(color)),
+                image_width,
+                image_height,
+                1,
+            )?;
+            file.flush()?;
+            drop(file);
+
+            fs::rename(&partial, &path)?;
         }
-    });
 
-    let world = &world;
-    let img: Vec<_> = (0..image_height)
-        .into_par_iter()
-        .rev()
-        .map(move |j| {
-            counter.fetch_sub(1, atomic::Ordering::Relaxed);
-            (0..image_width).into_par_iter().map(move |i| {
-                let mut color = Color::new(0.0, 0.0, 0.0);
-                let mut rng = thread_rng();
-
-                for _ in 0..samples_per_pixel {
-                    let u = (i as f64 + rng.gen_range(0.0..1.0)) / (image_width - 1) as f64;
-                    let v = (j as f64 + rng.gen_range(0.0..1.0)) / (image_height - 1) as f64;
-
-                    let ray = camera.get_ray(u, v);
-                    color += ray_color(&ray, &world[..], max_depth);
-                }
-
-                color
-            })
-        })
-        .flatten()
-        .collect();
+        return Ok(());
+    }
 
-    join_handle.join().expect("Failed to join status thread");
+    // camera, one of CameraBuilder for a perspective camera,
+    // OrthographicCameraBuilder, FisheyeCameraBuilder,
+    // EquirectangularCameraBuilder, StereoCameraBuilder or
+    // RealisticCameraBuilder::new(lens::read_prescription("lenses/dgauss50mm.txt")?),
+    // whose image brightness depends on its aperture. For scenes lit in
+    // physical units, expose photographically with e.g.
+    // .f_stop(16.0).shutter_speed(1.0 / 100.0).iso(100.0).white_balance(5500.0)
+    // Instead of the focus_dist, .focus_on(point) focuses on a point, and
+    // .build_focused(&world[..]) on whatever is in the center of the image.
+    // Any camera can be wrapped in LensImperfections to add distortion,
+    // chromatic aberration and vignetting, e.g.
+    // &LensImperfections::new(Box::new(camera_builder.build()), aspect_ratio)
+    //     .distortion(-0.02, 0.0, 0.0, 0.0, 0.0).chromatic_aberration(0.003)
+    let camera: &dyn Camera = &camera_builder.build();
+
+    eprintln!("{:#?}", camera);
+
+    let mut film = new_film();
+    let pixel_stats = render(&settings, camera, &world[..], &new_sampler(seed), &mut film);
+
+    let total_samples: u64 = pixel_stats.iter().map(|stats| stats.count() as u64).sum();
+    eprintln!(
+        "Took {:.2} samples per pixel on average",
+        total_samples as f64 / pixel_stats.len() as f64
+    );
+
+    if let (Some(adaptive), Some(path)) = (&settings.adaptive, heatmap_path) {
+        eprintln!("Outputting sample count heatmap to {}..", path);
+
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        to_ppm(
+            &mut file,
+            pixel_stats
+                .iter()
+                .map(|stats| adaptive.heatmap_color(stats.count())),
+            image_width,
+            image_height,
+            1,
+        )?;
+    }
 
     eprintln!("Outputting ppm image to stdout..");
 
+    // the film already normalizes the pixels by their filter weights
+    let exposure = camera.exposure();
     let stdout = std::io::stdout();
     to_ppm(
         &mut stdout.lock(),
-        img.into_iter(),
+        film.colors().into_iter().map(|color| exposure.apply(color)),
         image_width,
         image_height,
-        samples_per_pixel,
+        1,
     )?;
 
     Ok(())
@@ -1,15 +1,41 @@
 use crate::hit::HitRecord;
+use crate::medium::{Homogeneous, Medium};
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::texture::{SolidColor, Texture};
 use crate::vec3::{Color, Vec3};
-use rand::prelude::*;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The kind of lobe a ray was scattered by
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Lobe {
+    /// Scattered into a wide range of directions, e.g. by a diffuse surface or a medium
+    Diffuse,
+    /// Reflected or refracted into a single or a narrow range of directions
+    Specular,
+    /// Passed on unchanged, e.g. through the boundary of a volume
+    PassThrough,
+}
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Scatter {
     attenuation: Color,
     scattered: Ray,
+    lobe: Lobe,
 }
 
 impl Scatter {
+    /// Create a new diffuse Scatter
+    pub fn new(attenuation: Color, scattered: Ray) -> Self {
+        Self {
+            attenuation,
+            scattered,
+            lobe: Lobe::Diffuse,
+        }
+    }
+
     pub fn attenuation(&self) -> Color {
         self.attenuation
     }
@@ -17,10 +43,45 @@ impl Scatter {
     pub fn scattered(&self) -> &Ray {
         &self.scattered
     }
+
+    pub fn lobe(&self) -> Lobe {
+        self.lobe
+    }
+
+    /// Returns this Scatter with its direction randomly perturbed by up to roughness
+    ///
+    /// The direction is kept if the perturbed one would cross to the other side
+    /// of the surface with the given normal.
+    pub fn roughened(&self, roughness: f64, normal: Vec3, sampler: &mut dyn Sampler) -> Self {
+        let direction = self.scattered.direction();
+        let perturbed = direction.unit() + roughness * Vec3::random_in_unit_sphere(sampler);
+
+        if perturbed.dot(normal) * direction.dot(normal) <= 0.0 {
+            return self.clone();
+        }
+
+        Self {
+            scattered: Ray::new(self.scattered.origin(), perturbed),
+            ..self.clone()
+        }
+    }
 }
 
 pub trait Material: Send + Sync + std::fmt::Debug {
-    fn scatter(&self, ray: &Ray, hr: &HitRecord) -> Option<Scatter>;
+    fn scatter(&self, ray: &Ray, hr: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter>;
+
+    /// Returns the opacity in [0, 1] at the hit, rays pass through where it is below 1.0
+    fn opacity(&self, _hr: &HitRecord) -> f64 {
+        1.0
+    }
+
+    /// Returns the medium filling the inside of a closed surface made of this material
+    ///
+    /// Rays transmitted into the surface travel through this medium until they
+    /// leave through the surface again.
+    fn interior(&self) -> Option<&dyn Medium> {
+        None
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -35,8 +96,8 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray: &Ray, hr: &HitRecord) -> Option<Scatter> {
-        let scatter_direction = hr.normal() + Vec3::random_unit_vector();
+    fn scatter(&self, _ray: &Ray, hr: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        let scatter_direction = hr.normal() + Vec3::random_unit_vector(sampler);
 
         // catch degenerate scatter direction
         let scatter_direction = if scatter_direction.near_zero() {
@@ -48,6 +109,7 @@ impl Material for Lambertian {
         Some(Scatter {
             attenuation: self.albedo,
             scattered: Ray::new(hr.point(), scatter_direction),
+            lobe: Lobe::Diffuse,
         })
     }
 }
@@ -68,11 +130,11 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hr: &HitRecord) -> Option<Scatter> {
+    fn scatter(&self, ray: &Ray, hr: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
         let reflected = ray.direction().unit().reflect(hr.normal());
         let scattered = Ray::new(
             hr.point(),
-            reflected + self.fuzz * Vec3::random_in_unit_sphere(),
+            reflected + self.fuzz * Vec3::random_in_unit_sphere(sampler),
         );
 
         if scattered.direction().dot(hr.normal()) <= 0.0 {
@@ -81,6 +143,7 @@ impl Material for Metal {
             Some(Scatter {
                 attenuation: self.albedo,
                 scattered,
+                lobe: Lobe::Specular,
             })
         }
     }
@@ -108,7 +171,7 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, hr: &HitRecord) -> Option<Scatter> {
+    fn scatter(&self, ray: &Ray, hr: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
         let refraction_ratio = if hr.front_face() {
             1.0 / self.index_of_refraction
         } else {
@@ -120,7 +183,7 @@ impl Material for Dielectric {
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let direction = if refraction_ratio * sin_theta > 1.0
-            || self.reflectance(cos_theta, refraction_ratio) > thread_rng().gen_range(0.0..1.0)
+            || self.reflectance(cos_theta, refraction_ratio) > sampler.get_1d()
         {
             unit_direction.reflect(hr.normal())
         } else {
@@ -130,6 +193,816 @@ impl Material for Dielectric {
         Some(Scatter {
             attenuation: Color::new(1.0, 1.0, 1.0),
             scattered: Ray::new(hr.point(), direction),
+            lobe: Lobe::Specular,
+        })
+    }
+}
+
+/// Blends two materials by a weight in [0, 1]
+///
+/// A weight of 0.0 scatters like the first material only, a weight of 1.0
+/// like the second one only. Each scatter event picks one of the two materials
+/// at random, so the blend is correct on average.
+#[derive(Debug)]
+pub struct Mix {
+    first: Box<dyn Material>,
+    second: Box<dyn Material>,
+    weight: Box<dyn Texture>,
+}
+
+impl Mix {
+    pub fn new(first: Box<dyn Material>, second: Box<dyn Material>, weight: f64) -> Self {
+        let weight = Box::new(SolidColor::new(Color::new(weight, weight, weight)));
+
+        Self::textured(first, second, weight)
+    }
+
+    /// Create a new Mix whose weight is the mean of the color channels of a texture
+    pub fn textured(
+        first: Box<dyn Material>,
+        second: Box<dyn Material>,
+        weight: Box<dyn Texture>,
+    ) -> Self {
+        Self {
+            first,
+            second,
+            weight,
+        }
+    }
+
+    fn weight(&self, hr: &HitRecord) -> f64 {
+        let (u, v) = hr.uv();
+        let (r, g, b) = self.weight.value(u, v, hr.point()).xyz();
+
+        ((r + g + b) / 3.0).clamp(0.0, 1.0)
+    }
+}
+
+impl Material for Mix {
+    fn scatter(&self, ray: &Ray, hr: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        if self.weight(hr) > sampler.get_1d() {
+            self.second.scatter(ray, hr, sampler)
+        } else {
+            self.first.scatter(ray, hr, sampler)
+        }
+    }
+
+    fn opacity(&self, hr: &HitRecord) -> f64 {
+        let weight = self.weight(hr);
+
+        (1.0 - weight) * self.first.opacity(hr) + weight * self.second.opacity(hr)
+    }
+}
+
+/// Puts a clear dielectric coat over a base material
+///
+/// Rays hitting the coat from the outside are reflected specularly with the
+/// Fresnel reflectance of the coat and scatter off the base material otherwise.
+#[derive(Debug)]
+pub struct Coated {
+    coat: Dielectric,
+    base: Box<dyn Material>,
+}
+
+impl Coated {
+    pub fn new(base: Box<dyn Material>, index_of_refraction: f64) -> Self {
+        Self {
+            coat: Dielectric::new(index_of_refraction),
+            base,
+        }
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, ray: &Ray, hr: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        if !hr.front_face() {
+            return self.base.scatter(ray, hr, sampler);
+        }
+
+        let unit_direction = ray.direction().unit();
+        let cos_theta = (-unit_direction).dot(hr.normal()).min(1.0);
+        let refraction_ratio = 1.0 / self.coat.index_of_refraction;
+
+        if self.coat.reflectance(cos_theta, refraction_ratio) > sampler.get_1d() {
+            Some(Scatter {
+                attenuation: Color::new(1.0, 1.0, 1.0),
+                scattered: Ray::new(hr.point(), unit_direction.reflect(hr.normal())),
+                lobe: Lobe::Specular,
+            })
+        } else {
+            self.base.scatter(ray, hr, sampler)
+        }
+    }
+
+    fn opacity(&self, hr: &HitRecord) -> f64 {
+        self.base.opacity(hr)
+    }
+}
+
+/// Returns an orthonormal tangent and bitangent for a unit normal
+///
+/// The tangent follows the given direction as closely as possible. If that
+/// direction is degenerate, e.g. at the poles of a sphere, an arbitrary tangent
+/// is chosen.
+fn tangent_frame(normal: Vec3, direction: Vec3) -> (Vec3, Vec3) {
+    let tangent = direction - normal.dot(direction) * normal;
+
+    let tangent = if tangent.near_zero() {
+        let helper = if normal.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        normal.cross(helper).unit()
+    } else {
+        tangent.unit()
+    };
+
+    (tangent, normal.cross(tangent))
+}
+
+/// Perturbs the shading normal of a base material by a height texture
+///
+/// The height is the mean of the color channels of the texture, multiplied
+/// by scale.
+#[derive(Debug)]
+pub struct BumpMap {
+    base: Box<dyn Material>,
+    height: Box<dyn Texture>,
+    scale: f64,
+}
+
+impl BumpMap {
+    pub fn new(base: Box<dyn Material>, height: Box<dyn Texture>, scale: f64) -> Self {
+        Self {
+            base,
+            height,
+            scale,
+        }
+    }
+
+    fn height(&self, u: f64, v: f64, p: Vec3) -> f64 {
+        let (r, g, b) = self.height.value(u, v, p).xyz();
+        self.scale * (r + g + b) / 3.0
+    }
+}
+
+impl Material for BumpMap {
+    fn scatter(&self, ray: &Ray, hr: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        // finite differences of the height along u and v
+        let delta = 0.0005;
+        let (u, v) = hr.uv();
+        let normal = hr.outward_normal();
+
+        let displace = self.height(u, v, hr.point());
+        let u_displace = self.height(u + delta, v, hr.point() + delta * hr.dpdu());
+        let v_displace = self.height(u, v + delta, hr.point() + delta * hr.dpdv());
+
+        let dpdu = hr.dpdu() + (u_displace - displace) / delta * normal;
+        let dpdv = hr.dpdv() + (v_displace - displace) / delta * normal;
+        let bumped = dpdu.cross(dpdv);
+
+        if bumped.near_zero() {
+            return self.base.scatter(ray, hr, sampler);
+        }
+
+        // keep the bumped normal on the same side as the geometric one
+        let bumped = bumped.unit();
+        let bumped = if bumped.dot(normal) < 0.0 {
+            -bumped
+        } else {
+            bumped
+        };
+
+        self.base
+            .scatter(ray, &hr.with_outward_normal(bumped), sampler)
+    }
+
+    fn opacity(&self, hr: &HitRecord) -> f64 {
+        self.base.opacity(hr)
+    }
+}
+
+/// Replaces the shading normal of a base material by a tangent-space normal map
+///
+/// The color channels of the map, remapped from [0, 1] to [-1, 1], are the
+/// tangent, bitangent and normal components of the new normal.
+#[derive(Debug)]
+pub struct NormalMap {
+    base: Box<dyn Material>,
+    map: Box<dyn Texture>,
+}
+
+impl NormalMap {
+    pub fn new(base: Box<dyn Material>, map: Box<dyn Texture>) -> Self {
+        Self { base, map }
+    }
+}
+
+impl Material for NormalMap {
+    fn scatter(&self, ray: &Ray, hr: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        let (u, v) = hr.uv();
+        let normal = hr.outward_normal();
+        let (tangent, bitangent) = tangent_frame(normal, hr.dpdu());
+
+        let (x, y, z) = (2.0 * self.map.value(u, v, hr.point()) - Color::new(1.0, 1.0, 1.0)).xyz();
+        let mapped = x * tangent + y * bitangent + z * normal;
+
+        if mapped.near_zero() || mapped.dot(normal) <= 0.0 {
+            return self.base.scatter(ray, hr, sampler);
+        }
+
+        self.base
+            .scatter(ray, &hr.with_outward_normal(mapped.unit()), sampler)
+    }
+
+    fn opacity(&self, hr: &HitRecord) -> f64 {
+        self.base.opacity(hr)
+    }
+}
+
+/// Masks out parts of a base material by an opacity texture
+///
+/// The opacity is the mean of the color channels of the mask. Rays pass
+/// through fully transparent areas as if the surface was not there.
+#[derive(Debug)]
+pub struct Cutout {
+    base: Box<dyn Material>,
+    mask: Box<dyn Texture>,
+}
+
+impl Cutout {
+    pub fn new(base: Box<dyn Material>, mask: Box<dyn Texture>) -> Self {
+        Self { base, mask }
+    }
+}
+
+impl Material for Cutout {
+    fn scatter(&self, ray: &Ray, hr: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        self.base.scatter(ray, hr, sampler)
+    }
+
+    fn opacity(&self, hr: &HitRecord) -> f64 {
+        let (u, v) = hr.uv();
+        let (r, g, b) = self.mask.value(u, v, hr.point()).xyz();
+
+        ((r + g + b) / 3.0).clamp(0.0, 1.0) * self.base.opacity(hr)
+    }
+}
+
+/// Scatters light below the surface by a random walk through the interior
+///
+/// The surface itself is a smooth dielectric boundary. Inside, light travels
+/// a mean free path per color channel between scattering events, at each of
+/// which it keeps the fraction given by albedo. Only closed surfaces give
+/// meaningful results.
+#[derive(Debug)]
+pub struct Subsurface {
+    boundary: Dielectric,
+    medium: Homogeneous,
+}
+
+impl Subsurface {
+    pub fn new(albedo: Color, mean_free_path: Color, index_of_refraction: f64) -> Self {
+        Self {
+            boundary: Dielectric::new(index_of_refraction),
+            medium: Homogeneous::from_mean_free_path(albedo, mean_free_path),
+        }
+    }
+}
+
+impl Material for Subsurface {
+    fn scatter(&self, ray: &Ray, hr: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        self.boundary.scatter(ray, hr, sampler)
+    }
+
+    fn interior(&self) -> Option<&dyn Medium> {
+        Some(&self.medium)
+    }
+}
+
+/// Schlick's approximation of the Fresnel reflectance of a conductor with reflectance f0
+fn schlick_f0(f0: Color, cosine: f64) -> Color {
+    f0 + (Color::new(1.0, 1.0, 1.0) - f0) * (1.0 - cosine).max(0.0).powi(5)
+}
+
+/// A metal with separate roughness along the tangent and the bitangent, e.g. brushed metal
+///
+/// The tangent is the direction of increasing u on the surface. Reflections
+/// follow an anisotropic GGX microfacet distribution with the roughness alpha_x
+/// along the tangent and alpha_y along the bitangent.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnisotropicMetal {
+    albedo: Color,
+    alpha_x: f64,
+    alpha_y: f64,
+}
+
+impl AnisotropicMetal {
+    pub fn new(albedo: Color, alpha_x: f64, alpha_y: f64) -> Self {
+        Self {
+            albedo,
+            alpha_x: alpha_x.clamp(1e-4, 1.0),
+            alpha_y: alpha_y.clamp(1e-4, 1.0),
+        }
+    }
+
+    /// Smith's auxiliary function for a direction in the local shading frame
+    fn lambda(&self, w: Vec3) -> f64 {
+        let (x, y, z) = w.xyz();
+        let a2 = (self.alpha_x * x).powi(2) + (self.alpha_y * y).powi(2);
+
+        0.5 * (-1.0 + (1.0 + a2 / (z * z)).sqrt())
+    }
+
+    /// Sample a microfacet normal visible from the direction wo, both in the local shading frame
+    fn sample_visible_normal(&self, wo: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        // stretch the view direction to the hemisphere configuration
+        let vh = Vec3::new(self.alpha_x * wo.x(), self.alpha_y * wo.y(), wo.z()).unit();
+
+        let lensq = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if lensq > 0.0 {
+            Vec3::new(-vh.y(), vh.x(), 0.0) / lensq.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+
+        // sample the projected area of the visible hemisphere
+        let (u1, u2) = sampler.get_2d();
+        let r = u1.sqrt();
+        let phi = 2.0 * std::f64::consts::PI * u2;
+        let p1 = r * phi.cos();
+        let p2 = r * phi.sin();
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * p2;
+
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+
+        // unstretch back to the ellipsoid configuration
+        Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            nh.z().max(0.0),
+        )
+        .unit()
+    }
+}
+
+impl Material for AnisotropicMetal {
+    fn scatter(&self, ray: &Ray, hr: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        let normal = hr.normal();
+        let (tangent, bitangent) = tangent_frame(normal, hr.dpdu());
+
+        let to_local = |w: Vec3| Vec3::new(w.dot(tangent), w.dot(bitangent), w.dot(normal));
+
+        let wo = to_local(-ray.direction().unit());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let h = self.sample_visible_normal(wo, sampler);
+        let wi = 2.0 * wo.dot(h) * h - wo;
+        if wi.z() <= 0.0 {
+            return None;
+        }
+
+        // weight of visible normal sampling is F * G2 / G1
+        let lambda_o = self.lambda(wo);
+        let lambda_i = self.lambda(wi);
+        let g2_over_g1 = (1.0 + lambda_o) / (1.0 + lambda_o + lambda_i);
+
+        let direction = wi.x() * tangent + wi.y() * bitangent + wi.z() * normal;
+
+        Some(Scatter {
+            attenuation: schlick_f0(self.albedo, wo.dot(h)) * g2_over_g1,
+            scattered: Ray::new(hr.point(), direction),
+            lobe: Lobe::Specular,
+        })
+    }
+}
+
+/// A diffuse material with a sheen lobe at grazing angles, e.g. velvet or other cloth
+///
+/// The sheen follows the "Charlie" distribution of inverted microfacets with
+/// the given roughness in (0, 1].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sheen {
+    albedo: Color,
+    sheen: Color,
+    roughness: f64,
+}
+
+impl Sheen {
+    pub fn new(albedo: Color, sheen: Color, roughness: f64) -> Self {
+        Self {
+            albedo,
+            sheen,
+            roughness: roughness.clamp(1e-3, 1.0),
+        }
+    }
+}
+
+impl Material for Sheen {
+    fn scatter(&self, ray: &Ray, hr: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        let scatter_direction = hr.normal() + Vec3::random_unit_vector(sampler);
+
+        // catch degenerate scatter direction
+        let scatter_direction = if scatter_direction.near_zero() {
+            hr.normal()
+        } else {
+            scatter_direction
+        };
+
+        let wo = -ray.direction().unit();
+        let wi = scatter_direction.unit();
+        let h = wo + wi;
+
+        let cos_o = wo.dot(hr.normal()).max(1e-4);
+        let cos_i = wi.dot(hr.normal()).max(1e-4);
+        let cos_h = if h.near_zero() {
+            0.0
+        } else {
+            h.unit().dot(hr.normal()).clamp(0.0, 1.0)
+        };
+
+        // Charlie distribution with Neubelt's visibility term
+        let inv_r = 1.0 / self.roughness;
+        let sin_h = (1.0 - cos_h * cos_h).max(0.0).sqrt();
+        let d = (2.0 + inv_r) * sin_h.powf(inv_r) / (2.0 * std::f64::consts::PI);
+        let vis = 1.0 / (4.0 * (cos_i + cos_o - cos_i * cos_o));
+
+        // cosine weighted sampling cancels the cosine term up to a factor of pi
+        Some(Scatter {
+            attenuation: self.albedo + std::f64::consts::PI * d * vis * self.sheen,
+            scattered: Ray::new(hr.point(), scatter_direction),
+            lobe: Lobe::Diffuse,
         })
     }
 }
+
+/// Rotates a vector around a unit axis by an angle in radians
+fn rotate(v: Vec3, axis: Vec3, angle: f64) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+
+    v * cos + axis * v.dot(axis) * (1.0 - cos) + axis.cross(v) * sin
+}
+
+/// An isotropic measured BRDF in the MERL binary format
+///
+/// The BRDF is tabulated over the half angle and difference angle
+/// parametrization. Directions are importance sampled from a cosine weighted
+/// hemisphere, as no information about the shape of the lobes is available.
+#[derive(Clone, PartialEq)]
+pub struct Measured {
+    data: Vec<f64>,
+}
+
+impl Measured {
+    const THETA_H_RES: usize = 90;
+    const THETA_D_RES: usize = 90;
+    const PHI_D_RES: usize = 180;
+    const CHANNEL_SCALE: [f64; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+
+    /// Load a measured BRDF from a MERL .binary file
+    pub fn from_merl<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse_merl(&fs::read(path)?)
+    }
+
+    fn parse_merl(data: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        if data.len() < 12 {
+            return Err(invalid("truncated MERL header"));
+        }
+
+        let dims: Vec<i32> = data[..12]
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+
+        if dims != [Self::THETA_H_RES, Self::THETA_D_RES, Self::PHI_D_RES].map(|d| d as i32) {
+            return Err(invalid("unexpected MERL dimensions"));
+        }
+
+        let n = Self::THETA_H_RES * Self::THETA_D_RES * Self::PHI_D_RES;
+        let values = &data[12..];
+
+        if values.len() < 3 * n * 8 {
+            return Err(invalid("truncated MERL data"));
+        }
+
+        let data = values
+            .chunks_exact(8)
+            .take(3 * n)
+            .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+            .collect();
+
+        Ok(Self { data })
+    }
+
+    /// Evaluate the BRDF for two directions in the local shading frame
+    fn eval(&self, wi: Vec3, wo: Vec3) -> Color {
+        let half = (wi + wo).unit();
+        let theta_h = half.z().clamp(-1.0, 1.0).acos();
+        let phi_h = half.y().atan2(half.x());
+
+        // rotate the incoming direction so that the half vector is the normal
+        let diff = rotate(wi, Vec3::new(0.0, 0.0, 1.0), -phi_h);
+        let diff = rotate(diff, Vec3::new(0.0, 1.0, 0.0), -theta_h);
+        let theta_d = diff.z().clamp(-1.0, 1.0).acos();
+        let phi_d = diff.y().atan2(diff.x());
+
+        // the BRDF is symmetric under phi_d -> phi_d + pi
+        let phi_d = if phi_d < 0.0 {
+            phi_d + std::f64::consts::PI
+        } else {
+            phi_d
+        };
+
+        let half_pi = std::f64::consts::FRAC_PI_2;
+
+        // the half angle is sampled more densely close to the normal
+        let theta_h_idx = if theta_h <= 0.0 {
+            0
+        } else {
+            (((theta_h / half_pi).sqrt() * Self::THETA_H_RES as f64) as usize)
+                .min(Self::THETA_H_RES - 1)
+        };
+        let theta_d_idx =
+            ((theta_d / half_pi * Self::THETA_D_RES as f64) as usize).min(Self::THETA_D_RES - 1);
+        let phi_d_idx = ((phi_d / std::f64::consts::PI * Self::PHI_D_RES as f64) as usize)
+            .min(Self::PHI_D_RES - 1);
+
+        let idx = phi_d_idx + Self::PHI_D_RES * (theta_d_idx + Self::THETA_D_RES * theta_h_idx);
+        let n = Self::THETA_H_RES * Self::THETA_D_RES * Self::PHI_D_RES;
+        let [r, g, b] = Self::CHANNEL_SCALE;
+
+        // missing measurements are stored as negative values
+        Color::new(
+            self.data[idx] * r,
+            self.data[idx + n] * g,
+            self.data[idx + 2 * n] * b,
+        )
+        .map(|c| c.max(0.0))
+    }
+}
+
+impl std::fmt::Debug for Measured {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Measured")
+            .field("samples", &self.data.len())
+            .finish()
+    }
+}
+
+impl Material for Measured {
+    fn scatter(&self, ray: &Ray, hr: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        let normal = hr.normal();
+        let (tangent, bitangent) = tangent_frame(normal, hr.dpdu());
+
+        let to_local = |w: Vec3| Vec3::new(w.dot(tangent), w.dot(bitangent), w.dot(normal));
+
+        let scatter_direction = normal + Vec3::random_unit_vector(sampler);
+
+        // catch degenerate scatter direction
+        let scatter_direction = if scatter_direction.near_zero() {
+            normal
+        } else {
+            scatter_direction
+        };
+
+        let wo = to_local(-ray.direction().unit());
+        let wi = to_local(scatter_direction.unit());
+
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return None;
+        }
+
+        // cosine weighted sampling cancels the cosine term up to a factor of pi
+        Some(Scatter {
+            attenuation: std::f64::consts::PI * self.eval(wi, wo),
+            scattered: Ray::new(hr.point(), scatter_direction),
+            lobe: Lobe::Diffuse,
+        })
+    }
+}
+
+/// Wavelengths in nanometers representing the red, green and blue channels
+const CHANNEL_WAVELENGTHS: [f64; 3] = [630.0, 532.0, 465.0];
+
+/// The substrate below a thin film
+#[derive(Clone, Debug, PartialEq)]
+enum Substrate {
+    Dielectric(Dielectric),
+    Metal(Metal),
+}
+
+/// A thin transparent film on top of a dielectric or metal, e.g. soap bubbles or oil slicks
+///
+/// Light reflected at the top and the bottom of the film interferes, which
+/// modulates the reflectance by wavelength. The interference is evaluated at
+/// one representative wavelength per color channel. The metal substrate is
+/// approximated as a mirror with its albedo as reflectance.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThinFilm {
+    substrate: Substrate,
+    thickness: f64,
+    film_ior: f64,
+}
+
+impl ThinFilm {
+    /// Create a film of the given thickness in nanometers on top of a dielectric
+    pub fn dielectric(index_of_refraction: f64, thickness: f64, film_ior: f64) -> Self {
+        Self {
+            substrate: Substrate::Dielectric(Dielectric::new(index_of_refraction)),
+            thickness,
+            film_ior,
+        }
+    }
+
+    /// Create a film of the given thickness in nanometers on top of a metal
+    pub fn metal(albedo: Color, fuzz: f64, thickness: f64, film_ior: f64) -> Self {
+        Self {
+            substrate: Substrate::Metal(Metal::new(albedo, fuzz)),
+            thickness,
+            film_ior,
+        }
+    }
+
+    /// Returns the reflectance of the film for light coming from air
+    ///
+    /// The substrate is described by its Fresnel amplitude coefficients for s
+    /// and p polarized light given the cosine of the refracted angle in the film.
+    fn reflectance<F>(&self, cos_theta: f64, substrate: F) -> Color
+    where
+        F: Fn(f64, usize) -> (f64, f64),
+    {
+        let n1 = 1.0;
+        let n2 = self.film_ior;
+
+        let sin2_film = (n1 / n2).powi(2) * (1.0 - cos_theta * cos_theta);
+        if sin2_film >= 1.0 {
+            return Color::new(1.0, 1.0, 1.0);
+        }
+        let cos_film = (1.0 - sin2_film).sqrt();
+
+        let rs12 = (n1 * cos_theta - n2 * cos_film) / (n1 * cos_theta + n2 * cos_film);
+        let rp12 = (n2 * cos_theta - n1 * cos_film) / (n2 * cos_theta + n1 * cos_film);
+
+        // Airy summation of the multiple reflections inside the film
+        let airy = |r12: f64, r23: f64, delta: f64| {
+            let cross = 2.0 * r12 * r23 * delta.cos();
+            ((r12 * r12 + r23 * r23 + cross) / (1.0 + r12 * r12 * r23 * r23 + cross))
+                .clamp(0.0, 1.0)
+        };
+
+        let channel = |c: usize| {
+            let delta = 4.0 * std::f64::consts::PI * n2 * self.thickness * cos_film
+                / CHANNEL_WAVELENGTHS[c];
+            let (rs23, rp23) = substrate(cos_film, c);
+
+            0.5 * (airy(rs12, rs23, delta) + airy(rp12, rp23, delta))
+        };
+
+        Color::new(channel(0), channel(1), channel(2))
+    }
+}
+
+impl Material for ThinFilm {
+    fn scatter(&self, ray: &Ray, hr: &HitRecord, sampler: &mut dyn Sampler) -> Option<Scatter> {
+        let unit_direction = ray.direction().unit();
+        let cos_theta = (-unit_direction).dot(hr.normal()).clamp(0.0, 1.0);
+
+        match &self.substrate {
+            Substrate::Dielectric(dielectric) if hr.front_face() => {
+                let n2 = self.film_ior;
+                let n3 = dielectric.index_of_refraction;
+
+                let reflectance = self.reflectance(cos_theta, |cos_film, _| {
+                    let sin2_sub = (n2 / n3).powi(2) * (1.0 - cos_film * cos_film);
+                    if sin2_sub >= 1.0 {
+                        return (1.0, 1.0);
+                    }
+                    let cos_sub = (1.0 - sin2_sub).sqrt();
+
+                    (
+                        (n2 * cos_film - n3 * cos_sub) / (n2 * cos_film + n3 * cos_sub),
+                        (n3 * cos_film - n2 * cos_sub) / (n3 * cos_film + n2 * cos_sub),
+                    )
+                });
+
+                // choose by the mean reflectance and reweight per channel
+                let (r, g, b) = reflectance.xyz();
+                let p_reflect = ((r + g + b) / 3.0).clamp(1e-4, 1.0 - 1e-4);
+
+                if p_reflect > sampler.get_1d() {
+                    Some(Scatter {
+                        attenuation: reflectance / p_reflect,
+                        scattered: Ray::new(hr.point(), unit_direction.reflect(hr.normal())),
+                        lobe: Lobe::Specular,
+                    })
+                } else {
+                    let direction = unit_direction.refract(hr.normal(), 1.0 / n3);
+
+                    Some(Scatter {
+                        attenuation: (Color::new(1.0, 1.0, 1.0) - reflectance) / (1.0 - p_reflect),
+                        scattered: Ray::new(hr.point(), direction),
+                        lobe: Lobe::Specular,
+                    })
+                }
+            }
+            Substrate::Dielectric(dielectric) => dielectric.scatter(ray, hr, sampler),
+            Substrate::Metal(metal) => {
+                let scatter = metal.scatter(ray, hr, sampler)?;
+
+                // a mirror reflecting the albedo with a phase shift of pi
+                let albedo = [metal.albedo.x(), metal.albedo.y(), metal.albedo.z()];
+                let reflectance = self.reflectance(cos_theta, |_, c| {
+                    let r23 = -albedo[c].max(0.0).sqrt();
+                    (r23, r23)
+                });
+
+                Some(Scatter {
+                    attenuation: reflectance,
+                    ..scatter
+                })
+            }
+        }
+    }
+}
+
+/// An invisible boundary enclosing a participating medium, e.g. smoke or fog
+///
+/// Rays pass through the surface unchanged and travel through the medium
+/// while they are inside.
+#[derive(Debug)]
+pub struct Volume {
+    medium: Box<dyn Medium>,
+}
+
+impl Volume {
+    pub fn new(medium: Box<dyn Medium>) -> Self {
+        Self { medium }
+    }
+}
+
+impl Material for Volume {
+    fn scatter(&self, ray: &Ray, hr: &HitRecord, _sampler: &mut dyn Sampler) -> Option<Scatter> {
+        Some(Scatter {
+            attenuation: Color::new(1.0, 1.0, 1.0),
+            scattered: Ray::new(hr.point(), ray.direction()),
+            lobe: Lobe::PassThrough,
+        })
+    }
+
+    fn interior(&self) -> Option<&dyn Medium> {
+        Some(&*self.medium)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a MERL file with the given value for every sample of each channel
+    fn merl_file(dims: [i32; 3], values: [f64; 3]) -> Vec<u8> {
+        let n = Measured::THETA_H_RES * Measured::THETA_D_RES * Measured::PHI_D_RES;
+        let mut data: Vec<u8> = dims.iter().flat_map(|d| d.to_le_bytes()).collect();
+
+        for value in values {
+            for _ in 0..n {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn parse_merl() {
+        let brdf = Measured::parse_merl(&merl_file([90, 90, 180], [1500.0, 3000.0, -1.0]))
+            .expect("valid MERL file");
+        let color = brdf.eval(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.6, 0.0, 0.8));
+
+        // channels are scaled to the reflectance of the measurement
+        assert!((color.x() - 1.0).abs() < 1e-12);
+        assert!((color.y() - 2.3).abs() < 1e-12);
+        // missing measurements evaluate to zero
+        assert_eq!(color.z(), 0.0);
+    }
+
+    #[test]
+    fn reject_anisotropic_merl_dimensions() {
+        let error = Measured::parse_merl(&merl_file([90, 90, 360], [1.0; 3])).unwrap_err();
+
+        assert_eq!(error.to_string(), "unexpected MERL dimensions");
+    }
+
+    #[test]
+    fn reject_missing_blue_channel() {
+        let file = merl_file([90, 90, 180], [1.0; 3]);
+        let error = Measured::parse_merl(&file[..file.len() - 8]).unwrap_err();
+
+        assert_eq!(error.to_string(), "truncated MERL data");
+    }
+}
@@ -0,0 +1,578 @@
+use crate::material::Scatter;
+use crate::phase::{Isotropic, PhaseFunction};
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::{Color, Point3, Vec3};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The outcome of a ray travelling through a medium
+///
+/// Both variants carry the light emitted by the medium along the way.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Interaction {
+    /// The ray scattered inside the medium before reaching the next surface
+    Scatter { scatter: Scatter, emitted: Color },
+    /// The ray reached the next surface, weighted by the given transmittance
+    Transmit {
+        transmittance: Color,
+        emitted: Color,
+    },
+}
+
+pub trait Medium: Send + Sync + std::fmt::Debug {
+    /// Sample the next interaction of a ray travelling through the medium
+    ///
+    /// t_max is where the ray hits the next surface, which may be infinitely far away.
+    fn sample(&self, ray: &Ray, t_max: f64, sampler: &mut dyn Sampler) -> Interaction;
+}
+
+/// Returns the mean of the color channels
+fn mean(c: Color) -> f64 {
+    let (r, g, b) = c.xyz();
+    (r + g + b) / 3.0
+}
+
+/// Returns the per channel transmittance over a distance
+fn transmittance(sigma_t: Color, distance: f64) -> Color {
+    sigma_t.map(|s| if s > 0.0 { (-s * distance).exp() } else { 1.0 })
+}
+
+/// A medium with the same absorption and scattering coefficients everywhere
+///
+/// The coefficients are given per unit of distance and per color channel.
+/// Scattering is isotropic unless a different phase function is set.
+#[derive(Debug)]
+pub struct Homogeneous {
+    sigma_a: Color,
+    sigma_s: Color,
+    phase: Box<dyn PhaseFunction>,
+}
+
+impl Homogeneous {
+    pub fn new(sigma_a: Color, sigma_s: Color) -> Self {
+        Self {
+            sigma_a,
+            sigma_s,
+            phase: Box::new(Isotropic),
+        }
+    }
+
+    /// Scatter according to the given phase function
+    pub fn with_phase(self, phase: Box<dyn PhaseFunction>) -> Self {
+        Self { phase, ..self }
+    }
+
+    /// Create a medium from its single scattering albedo and mean free path
+    pub fn from_mean_free_path(albedo: Color, mean_free_path: Color) -> Self {
+        let sigma_t = mean_free_path.map(|mfp| if mfp > 0.0 { 1.0 / mfp } else { 0.0 });
+
+        Self::new(
+            (Color::new(1.0, 1.0, 1.0) - albedo) * sigma_t,
+            albedo * sigma_t,
+        )
+    }
+}
+
+impl Medium for Homogeneous {
+    fn sample(&self, ray: &Ray, t_max: f64, sampler: &mut dyn Sampler) -> Interaction {
+        let sigma_t = self.sigma_a + self.sigma_s;
+        let length = ray.direction().length();
+
+        // sample the distance in one randomly chosen channel, and weight by the
+        // pdf averaged over all channels so the result is correct for all of them.
+        // A single dimension selects the channel and is then reused for the distance.
+        let u = 3.0 * sampler.get_1d();
+        let channel = (u as usize).min(2);
+        let u = u - channel as f64;
+
+        let channel_sigma_t = match channel {
+            0 => sigma_t.x(),
+            1 => sigma_t.y(),
+            _ => sigma_t.z(),
+        };
+
+        let distance = if channel_sigma_t > 0.0 {
+            -(1.0 - u).ln() / channel_sigma_t
+        } else {
+            f64::INFINITY
+        };
+
+        if distance < t_max * length {
+            let tr = transmittance(sigma_t, distance);
+            let pdf = mean(sigma_t * tr);
+
+            Interaction::Scatter {
+                scatter: Scatter::new(
+                    self.sigma_s * tr / pdf,
+                    Ray::new(
+                        ray.at(distance / length),
+                        self.phase.sample(ray.direction(), sampler),
+                    ),
+                ),
+                emitted: Color::new(0.0, 0.0, 0.0),
+            }
+        } else {
+            let tr = transmittance(sigma_t, t_max * length);
+            let pdf = mean(tr);
+
+            Interaction::Transmit {
+                transmittance: if pdf > 0.0 {
+                    tr / pdf
+                } else {
+                    Color::new(0.0, 0.0, 0.0)
+                },
+                emitted: Color::new(0.0, 0.0, 0.0),
+            }
+        }
+    }
+}
+
+/// A homogeneous medium filling the whole scene outside of closed surfaces, e.g. haze or fog
+///
+/// The camera sits inside the atmosphere. Rays travel at most sky_distance
+/// through it along each path segment, so that rays escaping to the sky are
+/// attenuated like at a sky dome of that radius instead of vanishing entirely.
+#[derive(Debug)]
+pub struct Atmosphere {
+    medium: Homogeneous,
+    sky_distance: f64,
+}
+
+impl Atmosphere {
+    pub fn new(
+        sigma_a: Color,
+        sigma_s: Color,
+        phase: Box<dyn PhaseFunction>,
+        sky_distance: f64,
+    ) -> Self {
+        Self {
+            medium: Homogeneous::new(sigma_a, sigma_s).with_phase(phase),
+            sky_distance,
+        }
+    }
+}
+
+impl Medium for Atmosphere {
+    fn sample(&self, ray: &Ray, t_max: f64, sampler: &mut dyn Sampler) -> Interaction {
+        let t_sky = self.sky_distance / ray.direction().length();
+
+        self.medium.sample(ray, t_max.min(t_sky), sampler)
+    }
+}
+
+/// A regular 3D grid of values, e.g. densities or temperatures
+#[derive(Clone, Debug, PartialEq)]
+pub struct Grid {
+    dims: [usize; 3],
+    values: Vec<f64>,
+}
+
+impl Grid {
+    /// Create a grid from values stored with x varying fastest, then y, then z
+    ///
+    /// Panics if the number of values does not match the dimensions.
+    pub fn new(dims: [usize; 3], values: Vec<f64>) -> Self {
+        assert_eq!(
+            dims.iter().try_fold(1_usize, |n, &d| n.checked_mul(d)),
+            Some(values.len()),
+            "grid dimensions do not match the number of values"
+        );
+
+        Self { dims, values }
+    }
+
+    /// Load a grid from a raw file of little endian f32 values, x varying fastest
+    pub fn from_raw<P: AsRef<Path>>(path: P, dims: [usize; 3]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let data = fs::read(path)?;
+        let size = dims[0]
+            .checked_mul(dims[1])
+            .and_then(|n| n.checked_mul(dims[2]))
+            .and_then(|n| n.checked_mul(4))
+            .ok_or_else(|| invalid("grid dimensions too large"))?;
+
+        if data.len() != size {
+            return Err(invalid("raw grid size does not match the dimensions"));
+        }
+
+        let values = data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect();
+
+        Ok(Self { dims, values })
+    }
+
+    /// Returns the largest value in the grid
+    pub fn max(&self) -> f64 {
+        self.values.iter().cloned().fold(0.0, f64::max)
+    }
+
+    /// Look up the trilinearly interpolated value at local coordinates in [0, 1]^3
+    fn lookup(&self, p: Vec3) -> f64 {
+        let (x, y, z) = p.xyz();
+        let [nx, ny, nz] = self.dims;
+
+        if self.values.is_empty()
+            || !(0.0..=1.0).contains(&x)
+            || !(0.0..=1.0).contains(&y)
+            || !(0.0..=1.0).contains(&z)
+        {
+            return 0.0;
+        }
+
+        // values are located at the cell centers
+        let split = |c: f64, n: usize| {
+            let c = (c * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+            let i = (c as usize).min(n.saturating_sub(2));
+            (i, (i + 1).min(n - 1), c - i as f64)
+        };
+
+        let (x0, x1, fx) = split(x, nx);
+        let (y0, y1, fy) = split(y, ny);
+        let (z0, z1, fz) = split(z, nz);
+
+        let at = |i: usize, j: usize, k: usize| self.values[i + nx * (j + ny * k)];
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+
+        lerp(
+            lerp(
+                lerp(at(x0, y0, z0), at(x1, y0, z0), fx),
+                lerp(at(x0, y1, z0), at(x1, y1, z0), fx),
+                fy,
+            ),
+            lerp(
+                lerp(at(x0, y0, z1), at(x1, y0, z1), fx),
+                lerp(at(x0, y1, z1), at(x1, y1, z1), fx),
+                fy,
+            ),
+            fz,
+        )
+    }
+}
+
+/// One or more grids over a common box, loaded from a self-describing file
+///
+/// The file starts with the magic bytes `GRID`, followed by the dimensions as
+/// three u32, the min and max corners of the box as six f32 and the number of
+/// channels as u32. Then come the values of each channel in turn as f32, x
+/// varying fastest. All numbers are little endian.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GridFile {
+    min: Point3,
+    max: Point3,
+    channels: Vec<Grid>,
+}
+
+impl GridFile {
+    const MAGIC: &'static [u8] = b"GRID";
+    const HEADER_SIZE: usize = 4 + 3 * 4 + 6 * 4 + 4;
+
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    fn parse(data: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        if data.len() < Self::HEADER_SIZE {
+            return Err(invalid("truncated grid header"));
+        }
+        if &data[..4] != Self::MAGIC {
+            return Err(invalid("not a grid file"));
+        }
+
+        let word = |i: usize| {
+            let b = &data[4 + 4 * i..8 + 4 * i];
+            [b[0], b[1], b[2], b[3]]
+        };
+        let float = |i: usize| f32::from_le_bytes(word(i)) as f64;
+
+        let dims = [0, 1, 2].map(|i| u32::from_le_bytes(word(i)) as usize);
+        let min = Point3::new(float(3), float(4), float(5));
+        let max = Point3::new(float(6), float(7), float(8));
+        let channel_count = u32::from_le_bytes(word(9)) as usize;
+
+        let n = dims[0]
+            .checked_mul(dims[1])
+            .and_then(|n| n.checked_mul(dims[2]))
+            .ok_or_else(|| invalid("grid dimensions too large"))?;
+        let size = n
+            .checked_mul(channel_count)
+            .and_then(|n| n.checked_mul(4))
+            .ok_or_else(|| invalid("grid dimensions too large"))?;
+
+        let values = &data[Self::HEADER_SIZE..];
+
+        if values.len() != size {
+            return Err(invalid("grid size does not match the header"));
+        }
+
+        let channels = (0..channel_count)
+            .map(|c| {
+                let values = values[4 * n * c..4 * n * (c + 1)]
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+                    .collect();
+                Grid::new(dims, values)
+            })
+            .collect();
+
+        Ok(Self { min, max, channels })
+    }
+
+    pub fn min(&self) -> Point3 {
+        self.min
+    }
+
+    pub fn max(&self) -> Point3 {
+        self.max
+    }
+
+    /// Returns the grid of a channel, or None if the file has fewer channels
+    pub fn channel(&self, index: usize) -> Option<&Grid> {
+        self.channels.get(index)
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+}
+
+/// A heterogeneous medium with its density given by a grid, e.g. clouds or smoke
+///
+/// The grid spans the axis aligned box between min and max, outside of it the
+/// density is zero. The extinction coefficient is sigma_t times the density,
+/// of which the fraction albedo is scattered by the phase function, which is
+/// isotropic unless set otherwise. Light can be
+/// emitted by an emission grid or by a temperature grid radiating as a black
+/// body. Free-flight distances are sampled by delta tracking.
+#[derive(Debug)]
+pub struct GridMedium {
+    density: Grid,
+    min: Point3,
+    max: Point3,
+    sigma_t: f64,
+    albedo: Color,
+    phase: Box<dyn PhaseFunction>,
+    max_density: f64,
+    emission: Option<(Grid, Color)>,
+    temperature: Option<(Grid, f64)>,
+}
+
+impl GridMedium {
+    pub fn new(density: Grid, min: Point3, max: Point3, sigma_t: f64, albedo: Color) -> Self {
+        Self {
+            max_density: density.max(),
+            density,
+            min,
+            max,
+            sigma_t,
+            albedo,
+            phase: Box::new(Isotropic),
+            emission: None,
+            temperature: None,
+        }
+    }
+
+    /// Scatter according to the given phase function
+    pub fn with_phase(self, phase: Box<dyn PhaseFunction>) -> Self {
+        Self { phase, ..self }
+    }
+
+    /// Add light emitted per unit of distance, given by a grid times a color
+    pub fn with_emission(self, emission: Grid, color: Color) -> Self {
+        Self {
+            emission: Some((emission, color)),
+            ..self
+        }
+    }
+
+    /// Add black body emission from a grid of temperatures in Kelvin
+    ///
+    /// The emission is proportional to the absorption of the medium and to
+    /// the fourth power of the temperature, scaled by scale.
+    pub fn with_temperature(self, temperature: Grid, scale: f64) -> Self {
+        Self {
+            temperature: Some((temperature, scale)),
+            ..self
+        }
+    }
+
+    /// Transform a point to the local coordinates of the grids
+    fn to_local(&self, p: Point3) -> Vec3 {
+        let extent = self.max - self.min;
+        let (x, y, z) = (p - self.min).xyz();
+
+        Vec3::new(x / extent.x(), y / extent.y(), z / extent.z())
+    }
+
+    /// Clip the ray segment [0, t_max] to the bounds of the grid
+    fn clip(&self, ray: &Ray, t_max: f64) -> Option<(f64, f64)> {
+        let (ox, oy, oz) = ray.origin().xyz();
+        let (dx, dy, dz) = ray.direction().xyz();
+        let (minx, miny, minz) = self.min.xyz();
+        let (maxx, maxy, maxz) = self.max.xyz();
+
+        let mut t0 = 0.0_f64;
+        let mut t1 = t_max;
+
+        for &(o, d, min, max) in &[
+            (ox, dx, minx, maxx),
+            (oy, dy, miny, maxy),
+            (oz, dz, minz, maxz),
+        ] {
+            let inv_d = 1.0 / d;
+            let (near, far) = {
+                let a = (min - o) * inv_d;
+                let b = (max - o) * inv_d;
+                if a < b {
+                    (a, b)
+                } else {
+                    (b, a)
+                }
+            };
+
+            // NaN from a zero direction component inside the slab keeps the bounds
+            t0 = if near > t0 { near } else { t0 };
+            t1 = if far < t1 { far } else { t1 };
+
+            if t0 > t1 {
+                return None;
+            }
+        }
+
+        Some((t0, t1))
+    }
+
+    /// Returns the light emitted per unit of distance at a point in local coordinates
+    fn emitted(&self, p: Vec3, sigma_a: Color) -> Color {
+        let mut emitted = Color::new(0.0, 0.0, 0.0);
+
+        if let Some((grid, color)) = &self.emission {
+            emitted += grid.lookup(p) * *color;
+        }
+
+        if let Some((grid, scale)) = &self.temperature {
+            let kelvin = grid.lookup(p);
+
+            if kelvin > 0.0 {
+                emitted += scale * (kelvin / 1000.0).powi(4) * sigma_a * Color::blackbody(kelvin);
+            }
+        }
+
+        emitted
+    }
+}
+
+impl Medium for GridMedium {
+    fn sample(&self, ray: &Ray, t_max: f64, sampler: &mut dyn Sampler) -> Interaction {
+        let mut emitted = Color::new(0.0, 0.0, 0.0);
+        let transmit = |emitted| Interaction::Transmit {
+            transmittance: Color::new(1.0, 1.0, 1.0),
+            emitted,
+        };
+
+        let sigma_maj = self.sigma_t * self.max_density;
+        let (t0, t1) = match self.clip(ray, t_max) {
+            Some(segment) if sigma_maj > 0.0 => segment,
+            _ => return transmit(emitted),
+        };
+
+        let length = ray.direction().length();
+        let mut t = t0;
+
+        // delta tracking, tentative collisions against the majorant are
+        // accepted as real ones with probability density / max density
+        loop {
+            t -= (1.0 - sampler.get_1d()).ln() / (sigma_maj * length);
+
+            if t >= t1 {
+                return transmit(emitted);
+            }
+
+            let p = ray.at(t);
+            let local = self.to_local(p);
+            let sigma_t = self.sigma_t * self.density.lookup(local);
+            let sigma_a = sigma_t * (Color::new(1.0, 1.0, 1.0) - self.albedo);
+
+            emitted += self.emitted(local, sigma_a) / sigma_maj;
+
+            if sigma_t / sigma_maj > sampler.get_1d() {
+                return Interaction::Scatter {
+                    scatter: Scatter::new(
+                        self.albedo,
+                        Ray::new(p, self.phase.sample(ray.direction(), sampler)),
+                    ),
+                    emitted,
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid_file(dims: [u32; 3], bounds: [f32; 6], channels: &[&[f32]]) -> Vec<u8> {
+        let mut data = b"GRID".to_vec();
+
+        data.extend(dims.iter().flat_map(|d| d.to_le_bytes()));
+        data.extend(bounds.iter().flat_map(|b| b.to_le_bytes()));
+        data.extend((channels.len() as u32).to_le_bytes());
+        for channel in channels {
+            data.extend(channel.iter().flat_map(|v| v.to_le_bytes()));
+        }
+        data
+    }
+
+    #[test]
+    fn parse_grid_file() {
+        let data = grid_file(
+            [2, 1, 1],
+            [-1.0, 0.0, 0.0, 1.0, 2.0, 3.0],
+            &[&[0.25, 0.5], &[1000.0, 2000.0]],
+        );
+        let file = GridFile::parse(&data).expect("valid grid file");
+
+        assert_eq!(file.min(), Point3::new(-1.0, 0.0, 0.0));
+        assert_eq!(file.max(), Point3::new(1.0, 2.0, 3.0));
+        assert_eq!(file.channel_count(), 2);
+        assert_eq!(
+            file.channel(0),
+            Some(&Grid::new([2, 1, 1], vec![0.25, 0.5]))
+        );
+        assert_eq!(file.channel(1).map(Grid::max), Some(2000.0));
+        assert_eq!(file.channel(2), None);
+    }
+
+    #[test]
+    fn reject_file_without_magic() {
+        let data = grid_file([1, 1, 1], [0.0, 0.0, 0.0, 1.0, 1.0, 1.0], &[&[1.0]]);
+        let error = GridFile::parse(&[b"VDB!", &data[4..]].concat()).unwrap_err();
+
+        assert_eq!(error.to_string(), "not a grid file");
+    }
+
+    #[test]
+    fn reject_missing_channel() {
+        let mut data = grid_file([2, 2, 2], [0.0, 0.0, 0.0, 1.0, 1.0, 1.0], &[&[1.0; 8]]);
+        // claim a second channel without storing it
+        data[40..44].copy_from_slice(&2u32.to_le_bytes());
+        let error = GridFile::parse(&data).unwrap_err();
+
+        assert_eq!(error.to_string(), "grid size does not match the header");
+    }
+
+    #[test]
+    fn reject_overflowing_dimensions() {
+        let data = grid_file([u32::MAX; 3], [0.0, 0.0, 0.0, 1.0, 1.0, 1.0], &[]);
+        let error = GridFile::parse(&data).unwrap_err();
+
+        assert_eq!(error.to_string(), "grid dimensions too large");
+    }
+}
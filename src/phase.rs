@@ -0,0 +1,198 @@
+use crate::sampler::Sampler;
+use crate::vec3::Vec3;
+use std::f64::consts::PI;
+
+pub trait PhaseFunction: Send + Sync + std::fmt::Debug {
+    /// Evaluate the phase function for the cosine of the angle between the
+    /// direction of travel and the scattered direction
+    fn eval(&self, cos_theta: f64) -> f64;
+
+    /// Sample the cosine of the scattering angle proportionally to [`Self::eval()`]
+    fn sample_cos_theta(&self, sampler: &mut dyn Sampler) -> f64;
+
+    /// Sample a scattered direction for a ray travelling in the given direction
+    ///
+    /// The directions are distributed proportionally to [`Self::eval()`], so
+    /// that the phase function cancels out with the sampling density.
+    fn sample(&self, direction: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let cos_theta = self.sample_cos_theta(sampler).clamp(-1.0, 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * sampler.get_1d();
+
+        // orthonormal basis around the direction of travel
+        let w = direction.unit();
+        let helper = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let u = w.cross(helper).unit();
+        let v = w.cross(u);
+
+        sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w
+    }
+}
+
+/// Scatters into all directions equally
+#[derive(Clone, Debug, PartialEq)]
+pub struct Isotropic;
+
+impl PhaseFunction for Isotropic {
+    fn eval(&self, _cos_theta: f64) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn sample_cos_theta(&self, sampler: &mut dyn Sampler) -> f64 {
+        1.0 - 2.0 * sampler.get_1d()
+    }
+}
+
+/// The Henyey-Greenstein phase function
+///
+/// The anisotropy g in (-1, 1) is the mean cosine of the scattering angle,
+/// positive values scatter forward and negative values backward.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HenyeyGreenstein {
+    g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        Self {
+            g: g.clamp(-0.999, 0.999),
+        }
+    }
+}
+
+impl PhaseFunction for HenyeyGreenstein {
+    fn eval(&self, cos_theta: f64) -> f64 {
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+
+    fn sample_cos_theta(&self, sampler: &mut dyn Sampler) -> f64 {
+        let g = self.g;
+        let xi = sampler.get_1d();
+
+        if g.abs() < 1e-3 {
+            1.0 - 2.0 * xi
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+            (1.0 + g * g - s * s) / (2.0 * g)
+        }
+    }
+}
+
+/// A blend of two Henyey-Greenstein lobes, e.g. a forward and a backward one
+///
+/// weight in [0, 1] is the fraction of the first lobe.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DoubleHenyeyGreenstein {
+    first: HenyeyGreenstein,
+    second: HenyeyGreenstein,
+    weight: f64,
+}
+
+impl DoubleHenyeyGreenstein {
+    pub fn new(g1: f64, g2: f64, weight: f64) -> Self {
+        Self {
+            first: HenyeyGreenstein::new(g1),
+            second: HenyeyGreenstein::new(g2),
+            weight: weight.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl PhaseFunction for DoubleHenyeyGreenstein {
+    fn eval(&self, cos_theta: f64) -> f64 {
+        self.weight * self.first.eval(cos_theta) + (1.0 - self.weight) * self.second.eval(cos_theta)
+    }
+
+    fn sample_cos_theta(&self, sampler: &mut dyn Sampler) -> f64 {
+        if self.weight > sampler.get_1d() {
+            self.first.sample_cos_theta(sampler)
+        } else {
+            self.second.sample_cos_theta(sampler)
+        }
+    }
+}
+
+/// Scattering by particles much smaller than the wavelength, e.g. air molecules
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rayleigh;
+
+impl PhaseFunction for Rayleigh {
+    fn eval(&self, cos_theta: f64) -> f64 {
+        3.0 / (16.0 * PI) * (1.0 + cos_theta * cos_theta)
+    }
+
+    fn sample_cos_theta(&self, sampler: &mut dyn Sampler) -> f64 {
+        // analytic inversion of the cumulative distribution
+        let z = 2.0 * (2.0 * sampler.get_1d() - 1.0);
+        let u = -(z + (z * z + 1.0).sqrt()).cbrt();
+
+        u - 1.0 / u
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::Independent;
+
+    fn phase_functions() -> Vec<Box<dyn PhaseFunction>> {
+        vec![
+            Box::new(Isotropic),
+            Box::new(HenyeyGreenstein::new(0.7)),
+            Box::new(HenyeyGreenstein::new(-0.4)),
+            Box::new(DoubleHenyeyGreenstein::new(0.8, -0.3, 0.7)),
+            Box::new(Rayleigh),
+        ]
+    }
+
+    /// Integrate f(cos_theta) * eval(cos_theta) over the sphere
+    fn integrate(phase: &dyn PhaseFunction, f: impl Fn(f64) -> f64) -> f64 {
+        let steps = 100_000;
+        let d = 2.0 / steps as f64;
+
+        (0..steps)
+            .map(|i| {
+                let cos_theta = -1.0 + (i as f64 + 0.5) * d;
+                2.0 * PI * f(cos_theta) * phase.eval(cos_theta) * d
+            })
+            .sum()
+    }
+
+    #[test]
+    fn eval_is_normalized() {
+        for phase in phase_functions() {
+            let integral = integrate(&*phase, |_| 1.0);
+
+            assert!((integral - 1.0).abs() < 1e-3, "{:?}: {}", phase, integral);
+        }
+    }
+
+    #[test]
+    fn sample_follows_eval() {
+        let mut sampler = Independent::new(0);
+        let n = 100_000;
+
+        for phase in phase_functions() {
+            // compare the first two moments of the sampled cosines
+            let (mut mean, mut mean_sq) = (0.0, 0.0);
+            for _ in 0..n {
+                let cos_theta = phase.sample_cos_theta(&mut sampler);
+                mean += cos_theta / n as f64;
+                mean_sq += cos_theta * cos_theta / n as f64;
+            }
+
+            let expected = integrate(&*phase, |c| c);
+            let expected_sq = integrate(&*phase, |c| c * c);
+
+            assert!((mean - expected).abs() < 0.01, "{:?}", phase);
+            assert!((mean_sq - expected_sq).abs() < 0.01, "{:?}", phase);
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use rand::prelude::*;
+
+/// Generates the random numbers of the samples taken for a pixel
+///
+/// Each call to [`Self::get_1d()`] or [`Self::get_2d()`] consumes the next
+/// dimension of the current sample. As long as every sample consumes its
+/// dimensions in the same order, e.g. the pixel position first, then the lens
+/// position, then the dimensions of each path vertex, samplers can distribute
+/// the samples of each dimension well across the samples of a pixel.
+pub trait Sampler {
+    /// Start generating the sample with the given index for a pixel
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: u32);
+
+    /// Returns the next dimension of the current sample in [0, 1)
+    fn get_1d(&mut self) -> f64;
+
+    /// Returns the next two dimensions of the current sample in [0, 1)
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+/// The largest f64 below 1.0
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+/// Mixes the bits of a 64 bit value (splitmix64 finalizer)
+fn mix_bits(mut v: u64) -> u64 {
+    v = (v ^ (v >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    v = (v ^ (v >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    v ^ (v >> 31)
+}
+
+/// Hashes a number of values to a 64 bit value
+fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |h, &v| {
+        mix_bits(h ^ v.wrapping_add(0x9e37_79b9_7f4a_7c15))
+    })
+}
+
+/// Converts the upper bits of a hash to a value in [0, 1)
+fn hash_to_unit(h: u64) -> f64 {
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Converts a 32 bit fixed point fraction to a value in [0, 1)
+fn fraction_to_unit(x: u32) -> f64 {
+    (x as f64 / (1u64 << 32) as f64).min(ONE_MINUS_EPSILON)
+}
+
+/// Returns the element at position i of a pseudo random permutation of [0, n)
+///
+/// The permutation is selected by seed (Kensler, "Correlated Multi-Jittered Sampling").
+fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+
+        if i < n {
+            break i.wrapping_add(seed) % n;
+        }
+    }
+}
+
+/// Draws every dimension independently at random
+///
+/// The random number generator is reseeded from the seed, the pixel and the
+/// sample index at the start of every sample, so that the samples do not depend
+/// on the order in which pixels are rendered.
+#[derive(Clone, Debug)]
+pub struct Independent {
+    seed: u64,
+    rng: StdRng,
+}
+
+impl Independent {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Sampler for Independent {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: u32) {
+        let (x, y) = pixel;
+        self.rng = StdRng::seed_from_u64(hash(&[x as u64, y as u64, index as u64, self.seed]));
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.rng.gen_range(0.0..1.0)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.gen_range(0.0..1.0), self.rng.gen_range(0.0..1.0))
+    }
+}
+
+/// Places each sample of a pixel into its own stratum, per dimension
+///
+/// 1D dimensions are split into samples_per_pixel intervals, 2D dimensions into
+/// a grid of at least samples_per_pixel cells. Samples are jittered inside their
+/// stratum, and the strata are shuffled independently for every dimension.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stratified {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: (usize, usize),
+    index: u32,
+    dimension: u64,
+}
+
+impl Stratified {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        Self {
+            samples_per_pixel: samples_per_pixel.max(1),
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    /// Returns the hash of the current pixel and dimension, and the hash of the current sample
+    fn next_hashes(&mut self) -> (u64, u64) {
+        let (x, y) = self.pixel;
+        let dimension_hash = hash(&[x as u64, y as u64, self.dimension, self.seed]);
+        let sample_hash = hash(&[dimension_hash, self.index as u64]);
+        self.dimension += 1;
+
+        (dimension_hash, sample_hash)
+    }
+}
+
+impl Sampler for Stratified {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: u32) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (dimension_hash, sample_hash) = self.next_hashes();
+        let n = self.samples_per_pixel;
+
+        // samples beyond samples_per_pixel start another round of strata
+        let round = (self.index / n) as u64;
+        let stratum = permutation_element(self.index % n, n, (dimension_hash ^ round) as u32);
+
+        ((stratum as f64 + hash_to_unit(sample_hash)) / n as f64).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (dimension_hash, sample_hash) = self.next_hashes();
+        let nx = (self.samples_per_pixel as f64).sqrt().ceil() as u32;
+        let ny = self.samples_per_pixel.div_ceil(nx);
+        let n = nx * ny;
+
+        let round = (self.index / n) as u64;
+        let cell = permutation_element(self.index % n, n, (dimension_hash ^ round) as u32);
+        let (cx, cy) = (cell % nx, cell / nx);
+
+        let jx = hash_to_unit(sample_hash);
+        let jy = hash_to_unit(mix_bits(sample_hash));
+
+        (
+            ((cx as f64 + jx) / nx as f64).min(ONE_MINUS_EPSILON),
+            ((cy as f64 + jy) / ny as f64).min(ONE_MINUS_EPSILON),
+        )
+    }
+}
+
+/// The first primes, used as bases of the dimensions of the Halton sequence
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+/// Returns the radical inverse of an index in the given base
+fn radical_inverse(base: u32, mut index: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_n = 1.0;
+    let mut reversed = 0.0;
+
+    while index > 0 {
+        let digit = index % base as u64;
+        index /= base as u64;
+        reversed = reversed * base as f64 + digit as f64;
+        inv_base_n *= inv_base;
+    }
+
+    (reversed * inv_base_n).min(ONE_MINUS_EPSILON)
+}
+
+/// Takes the samples of a pixel from the Halton sequence
+///
+/// Each dimension uses the next prime as base and is randomly shifted per
+/// pixel (Cranley-Patterson rotation), so that neighbouring pixels are
+/// decorrelated. Dimensions beyond the available primes are drawn from a hash.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Halton {
+    seed: u64,
+    pixel: (usize, usize),
+    index: u32,
+    dimension: usize,
+}
+
+impl Halton {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for Halton {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: u32) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (x, y) = self.pixel;
+        let shift = hash(&[x as u64, y as u64, self.dimension as u64, self.seed]);
+
+        let value = match PRIMES.get(self.dimension) {
+            Some(&base) => {
+                let value = radical_inverse(base, self.index as u64) + hash_to_unit(shift);
+                value.fract().min(ONE_MINUS_EPSILON)
+            }
+            None => hash_to_unit(hash(&[shift, self.index as u64])),
+        };
+
+        self.dimension += 1;
+        value
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/// Applies a nested uniform (Owen) scramble to a 32 bit fraction
+///
+/// This is the hash based approximation by Burley, "Practical Hash-based Owen Scrambling".
+fn owen_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+/// Returns the first two dimensions of the Sobol sequence as 32 bit fractions
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let mut y = 0;
+    let mut v = 1 << 31;
+    let mut i = index;
+
+    while i > 0 {
+        if i & 1 == 1 {
+            y ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+
+    (index.reverse_bits(), y)
+}
+
+/// Takes the samples of a pixel from the Owen scrambled Sobol sequence
+///
+/// Every 1D or 2D request uses the first dimensions of the Sobol sequence,
+/// with the sample order shuffled and the values scrambled by a hash of the
+/// pixel and the dimension. This keeps the stratification of the Sobol
+/// sequence in each request while decorrelating the dimensions.
+///
+/// The samples are shuffled within blocks of samples_per_pixel rounded up to a
+/// power of two. Taking fewer samples than that from a block, e.g. 10 of 16,
+/// leaves the samples only roughly stratified, so powers of two work best.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sobol {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel: (usize, usize),
+    index: u32,
+    dimension: u64,
+}
+
+impl Sobol {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        Self {
+            samples_per_pixel: samples_per_pixel.max(1).next_power_of_two(),
+            seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    /// Returns the shuffled index of the current sample and a hash to scramble it with
+    fn next_index(&mut self) -> (u32, u64) {
+        let (x, y) = self.pixel;
+        let h = hash(&[x as u64, y as u64, self.dimension, self.seed]);
+        self.dimension += 1;
+
+        // shuffle within each block of samples_per_pixel samples
+        let n = self.samples_per_pixel;
+        let block = self.index - self.index % n;
+        let index = block + permutation_element(self.index % n, n, h as u32);
+
+        (index, mix_bits(h))
+    }
+}
+
+impl Sampler for Sobol {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: u32) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (index, h) = self.next_index();
+        let (x, _) = sobol_2d(index);
+
+        fraction_to_unit(owen_scramble(x, h as u32))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (index, h) = self.next_index();
+        let (x, y) = sobol_2d(index);
+
+        (
+            fraction_to_unit(owen_scramble(x, h as u32)),
+            fraction_to_unit(owen_scramble(y, (h >> 32) as u32)),
+        )
+    }
+}
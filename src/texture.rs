@@ -0,0 +1,257 @@
+use crate::vec3::{Color, Point3};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub trait Texture: Send + Sync + std::fmt::Debug {
+    /// Returns the color of the texture at the surface coordinates (u, v) and the point p
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SolidColor {
+    color: Color,
+}
+
+impl SolidColor {
+    pub fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: Point3) -> Color {
+        self.color
+    }
+}
+
+#[derive(Debug)]
+pub struct Checker {
+    odd: Box<dyn Texture>,
+    even: Box<dyn Texture>,
+    scale: f64,
+}
+
+impl Checker {
+    pub fn new(odd: Box<dyn Texture>, even: Box<dyn Texture>, scale: f64) -> Self {
+        Self { odd, even, scale }
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        let sines =
+            (self.scale * p.x()).sin() * (self.scale * p.y()).sin() * (self.scale * p.z()).sin();
+
+        if sines < 0.0 {
+            self.odd.value(u, v, p)
+        } else {
+            self.even.value(u, v, p)
+        }
+    }
+}
+
+/// A texture backed by an image, looked up with nearest neighbour filtering
+///
+/// The color values are normalized to [0, 1] but otherwise used as stored, no
+/// gamma decoding takes place. This makes the texture usable for data such as
+/// normal maps or masks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl ImageTexture {
+    /// Load an image texture from a plain (P3) or binary (P6) ppm file
+    pub fn from_ppm<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse_ppm(&fs::read(path)?)
+    }
+
+    fn parse_ppm(data: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let mut pos = 0;
+        let mut header = Vec::new();
+
+        // the header consists of the magic number, width, height and maxval
+        while header.len() < 4 {
+            while pos < data.len() && data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+
+            if pos < data.len() && data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+                continue;
+            }
+
+            let start = pos;
+            while pos < data.len() && !data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+
+            if start == pos {
+                return Err(invalid("truncated ppm header"));
+            }
+
+            header.push(
+                std::str::from_utf8(&data[start..pos])
+                    .map_err(|_| invalid("invalid ppm header"))?,
+            );
+        }
+
+        let parse = |s: &str| {
+            s.parse::<usize>()
+                .map_err(|_| invalid("invalid number in ppm file"))
+        };
+
+        let width = parse(header[1])?;
+        let height = parse(header[2])?;
+        let maxval = parse(header[3])?;
+
+        if maxval == 0 || maxval > 65535 {
+            return Err(invalid("invalid ppm maxval"));
+        }
+
+        let sample_count = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(3))
+            .ok_or_else(|| invalid("ppm dimensions too large"))?;
+
+        let samples: Vec<usize> = match header[0] {
+            "P3" => std::str::from_utf8(&data[pos..])
+                .map_err(|_| invalid("invalid ppm data"))?
+                .split_ascii_whitespace()
+                .take(sample_count)
+                .map(parse)
+                .collect::<io::Result<_>>()?,
+            "P6" => {
+                // exactly one whitespace character separates the header from the data
+                let data = data.get(pos + 1..).unwrap_or_default();
+
+                if maxval < 256 {
+                    data.iter()
+                        .take(sample_count)
+                        .map(|&b| b as usize)
+                        .collect()
+                } else {
+                    data.chunks_exact(2)
+                        .take(sample_count)
+                        .map(|b| (b[0] as usize) << 8 | b[1] as usize)
+                        .collect()
+                }
+            }
+            _ => return Err(invalid("unsupported ppm format")),
+        };
+
+        if samples.len() < sample_count {
+            return Err(invalid("truncated ppm data"));
+        }
+
+        let scale = 1.0 / maxval as f64;
+        let pixels = samples
+            .chunks_exact(3)
+            .map(|c| Color::new(c[0] as f64, c[1] as f64, c[2] as f64) * scale)
+            .collect();
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: Point3) -> Color {
+        if self.pixels.is_empty() {
+            return Color::new(0.0, 1.0, 1.0);
+        }
+
+        // the image origin is at the top left, v = 0 is at the bottom
+        let u = u.clamp(0.0, 1.0);
+        let v = 1.0 - v.clamp(0.0, 1.0);
+
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+
+        self.pixels[j * self.width + i]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_plain_ppm() {
+        let image = ImageTexture::parse_ppm(b"P3\n# comment\n2 1\n255\n255 0 0  0 0 255\n")
+            .expect("valid ppm");
+
+        assert_eq!((image.width(), image.height()), (2, 1));
+        assert_eq!(image.pixels[0], Color::new(1.0, 0.0, 0.0));
+        assert_eq!(image.pixels[1], Color::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn parse_binary_ppm() {
+        let mut data = b"P6 1 2 255\n".to_vec();
+        data.extend_from_slice(&[0, 255, 0, 255, 255, 255]);
+        let image = ImageTexture::parse_ppm(&data).expect("valid ppm");
+
+        // v = 0 is the bottom row
+        assert_eq!(
+            image.value(0.5, 1.0, Point3::new(0.0, 0.0, 0.0)),
+            Color::new(0.0, 1.0, 0.0)
+        );
+        assert_eq!(
+            image.value(0.5, 0.0, Point3::new(0.0, 0.0, 0.0)),
+            Color::new(1.0, 1.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn parse_16_bit_ppm() {
+        let mut data = b"P6 1 1 65535\n".to_vec();
+        data.extend_from_slice(&[0xff, 0xff, 0, 0, 0x80, 0]);
+        let image = ImageTexture::parse_ppm(&data).expect("valid ppm");
+
+        // samples are big endian
+        assert_eq!(image.pixels[0].x(), 1.0);
+        assert_eq!(image.pixels[0].y(), 0.0);
+        assert!((image.pixels[0].z() - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn reject_truncated_pixel_data() {
+        let error = ImageTexture::parse_ppm(b"P3 2 2 255\n1 2 3").unwrap_err();
+
+        assert_eq!(error.to_string(), "truncated ppm data");
+    }
+
+    #[test]
+    fn reject_grayscale_ppm() {
+        let error = ImageTexture::parse_ppm(b"P5 1 1 255\n\x80").unwrap_err();
+
+        assert_eq!(error.to_string(), "unsupported ppm format");
+    }
+
+    #[test]
+    fn reject_overflowing_dimensions() {
+        let header = format!("P6 {} 3 255\n", usize::MAX / 2);
+        let error = ImageTexture::parse_ppm(header.as_bytes()).unwrap_err();
+
+        assert_eq!(error.to_string(), "ppm dimensions too large");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::sampler::Sampler;
 use rand::prelude::*;
 use std::io;
 use std::ops;
@@ -5,7 +6,7 @@ use std::ops;
 pub type Point3 = Vec3;
 pub type Color = Vec3;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct Vec3(f64, f64, f64);
 
 impl Vec3 {
@@ -15,9 +16,7 @@ impl Vec3 {
     }
 
     /// Generate a random 3-dimensional vector in [0..1), [0..1), [0..1)
-    pub fn random() -> Self {
-        let mut rng = thread_rng();
-
+    pub fn random<R: Rng>(rng: &mut R) -> Self {
         Self(
             rng.gen_range(0.0..1.0),
             rng.gen_range(0.0..1.0),
@@ -26,9 +25,7 @@ impl Vec3 {
     }
 
     /// Generate a random 3-dimensional vector in [range), [range), [range)
-    pub fn random_range(range: ops::Range<f64>) -> Self {
-        let mut rng = thread_rng();
-
+    pub fn random_range<R: Rng>(rng: &mut R, range: ops::Range<f64>) -> Self {
         Self(
             rng.gen_range(range.clone()),
             rng.gen_range(range.clone()),
@@ -38,37 +35,80 @@ impl Vec3 {
 
     /// Generate a random 3-dimensional vector which is inside the unit sphere
     ///
-    /// Note that this functions loops until a random vector inside the unit
-    /// sphere has been found.
-    pub fn random_in_unit_sphere() -> Self {
-        loop {
-            let p = Self::random_range(-1.0..1.0);
-            if p.length_squared() < 1.0 {
-                break p;
-            }
-        }
+    /// Note that this function consumes three dimensions of the sampler.
+    pub fn random_in_unit_sphere(sampler: &mut dyn Sampler) -> Self {
+        let radius = sampler.get_1d().cbrt();
+        radius * Self::random_unit_vector(sampler)
     }
 
-    /// Generate the unit vector of a random 3-dimensional vector which is inside the unit sphere
-    ///
-    /// Note that this function calls [`Self::random_in_unit_sphere()`]
-    pub fn random_unit_vector() -> Self {
-        Self::random_in_unit_sphere().unit()
+    /// Generate a random unit vector, uniformly distributed on the unit sphere
+    pub fn random_unit_vector(sampler: &mut dyn Sampler) -> Self {
+        let (u1, u2) = sampler.get_2d();
+
+        let z = 1.0 - 2.0 * u1;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * u2;
+
+        Self(r * phi.cos(), r * phi.sin(), z)
     }
 
     /// Generate a random vector inside the unit disk
     ///
-    /// Note that this functino loops until a random vector inside the unit
-    /// disk has been found.
-    pub fn random_in_unit_disk() -> Self {
-        let mut rng = thread_rng();
-
-        loop {
-            let p = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
-            if p.length_squared() < 1.0 {
-                break p;
-            }
+    /// Note that this function uses the concentric mapping of the unit square
+    /// to the disk, which preserves the stratification of the sampler.
+    pub fn random_in_unit_disk(sampler: &mut dyn Sampler) -> Self {
+        let (u1, u2) = sampler.get_2d();
+        let (x, y) = (2.0 * u1 - 1.0, 2.0 * u2 - 1.0);
+
+        if x == 0.0 && y == 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
         }
+
+        let (r, theta) = if x.abs() > y.abs() {
+            (x, std::f64::consts::FRAC_PI_4 * (y / x))
+        } else {
+            (
+                y,
+                std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (x / y),
+            )
+        };
+
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+    }
+
+    /// Approximate the linear color of a black body at the given temperature in Kelvin
+    ///
+    /// The color is normalized so that its brightest channel is 1.0, which makes it
+    /// usable both for emission and for white balancing.
+    pub fn blackbody(kelvin: f64) -> Color {
+        // fit by Tanner Helland, valid from 1000K to 40000K
+        let t = kelvin.clamp(1000.0, 40000.0) / 100.0;
+
+        let r = if t <= 66.0 {
+            255.0
+        } else {
+            329.698727446 * (t - 60.0).powf(-0.1332047592)
+        };
+
+        let g = if t <= 66.0 {
+            99.4708025861 * t.ln() - 161.1195681661
+        } else {
+            288.1221695283 * (t - 60.0).powf(-0.0755148492)
+        };
+
+        let b = if t >= 66.0 {
+            255.0
+        } else if t <= 19.0 {
+            0.0
+        } else {
+            138.5177312231 * (t - 10.0).ln() - 305.0447927307
+        };
+
+        // the fit is gamma encoded, decode it for gamma = 2.0
+        let color = Color::new(r, g, b).map(|c| (c / 255.0).clamp(0.0, 1.0).powi(2));
+        let max = color.x().max(color.y()).max(color.z());
+
+        color / max
     }
 
     /// Calculates whether self is near zero
@@ -140,6 +180,11 @@ impl Vec3 {
         )
     }
 
+    /// Apply a function to each component of a vector
+    pub fn map<F: Fn(f64) -> f64>(self, f: F) -> Self {
+        Self(f(self.0), f(self.1), f(self.2))
+    }
+
     /// Calculate the unit-vector of self
     pub fn unit(self) -> Self {
         self / self.length()